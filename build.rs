@@ -1,40 +1,431 @@
 use std::env;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::path::{ Path, PathBuf };
+#[cfg(feature = "vendored")]
+use std::process::Command;
 
 use unwrap::unwrap;
 
 
-const FUSE_USE_VERSION: u32 = 34;
+// the API levels selected by the `fuse_3_*` features, newest first:
+// (feature, FUSE_USE_VERSION, oldest libfuse providing it)
+const API_LEVELS: &[(&str, u32, &str)] = &[
+    ("fuse_3_16", 316, "3.16.0"),
+    ("fuse_3_12", 312, "3.12.0"),
+    ("", 34, "3.8.0"),
+];
+
+// the API level of the `fuse2` feature, built against libfuse 2 found as `fuse`
+const FUSE2_LEVEL: (&str, u32, &str) = ("fuse2", 29, "2.9.0");
+
+// the libfuse release expected in `libfuse/` by the `vendored` feature
+#[cfg(feature = "vendored")]
+const VENDORED_VERSION: &str = "3.16.2";
+
+// the `fuse_config.h` meson generates for Linux, minus the optional backtraces
+#[cfg(feature = "vendored")]
+const VENDORED_CONFIG: &str = "\
+#define HAVE_COPY_FILE_RANGE 1
+#define HAVE_FALLOCATE 1
+#define HAVE_FDATASYNC 1
+#define HAVE_FORK 1
+#define HAVE_FSTATAT 1
+#define HAVE_ICONV 1
+#define HAVE_OPENAT 1
+#define HAVE_PIPE2 1
+#define HAVE_POSIX_FALLOCATE 1
+#define HAVE_READLINKAT 1
+#define HAVE_SETXATTR 1
+#define HAVE_SPLICE 1
+#define HAVE_STRUCT_STAT_ST_ATIM 1
+#define HAVE_UTIMENSAT 1
+#define HAVE_VMSPLICE 1
+";
 
 
 fn main() {
-    let fuse = unwrap!(pkg_config::Config::new()
-        .atleast_version("3.8.0")
-        .probe("fuse3"));
+    let level = *unwrap!(API_LEVELS
+        .iter()
+        .find(|(feature, ..)| feature.is_empty() || feature_enabled(feature)));
+
+    let out_path = PathBuf::from(unwrap!(env::var("OUT_DIR")));
+    let bindings_path = out_path.join("bindings.rs");
+
+    // libfuse 2 only goes with the default API level and a system libfuse
+    if cfg!(feature = "fuse2") && (!level.0.is_empty() || cfg!(feature = "vendored")) {
+        let other = if level.0.is_empty() { "vendored" } else { level.0 };
+
+        fail(&bindings_path, &format!("the `fuse2` feature excludes the `{}` feature", other));
+
+        return;
+    }
+
+    let (package, (feature, use_version, min_version), oldest) = if cfg!(feature = "fuse2") {
+        ("fuse", FUSE2_LEVEL, FUSE2_LEVEL.2)
+    } else {
+        ("fuse3", level, unwrap!(API_LEVELS.last()).2)
+    };
+
+    let shipped_path = shipped_bindings(use_version);
+
+    println!("cargo:rerun-if-env-changed=LIBFUSE_SYS_PREGENERATED");
+    println!("cargo:rerun-if-env-changed=LIBFUSE_SYS_UPDATE_PREGENERATED");
+    println!("cargo:rustc-check-cfg=cfg(generated_bindings)");
+    println!("cargo:rustc-check-cfg=cfg(fuse_loop_mt_32)");
+    println!("cargo:rustc-check-cfg=cfg(fuse_make_version_100)");
+
+    if shipped_path.exists() {
+        println!("cargo:rerun-if-changed={}", shipped_path.display());
+    }
+
+    // docs builders have neither libfuse nor libclang, and nothing gets linked,
+    // they need bindings generated beforehand
+    if env::var_os("DOCS_RS").is_some() {
+        copy_shipped(&shipped_path, &bindings_path);
+        header_cfgs(bindings_version(&bindings_path).as_deref(), use_version);
+
+        return;
+    }
+
+    let pregenerated = feature_enabled("pregenerated")
+        || env::var_os("LIBFUSE_SYS_PREGENERATED").is_some()
+        || !cfg!(feature = "bindgen");
+
+    let fuse = if cfg!(feature = "vendored") {
+        build_vendored(&out_path)
+    } else {
+        match pkg_config::Config::new().atleast_version(oldest).probe(package) {
+            Ok(fuse) => Fuse {
+                version: fuse.version,
+                include_paths: fuse.include_paths,
+                root: None,
+            },
+            // no headers to check the shipped bindings against, link what the linker finds
+            Err(_) if pregenerated => {
+                println!("cargo:rustc-link-lib={}", package);
+                copy_shipped(&shipped_path, &bindings_path);
+                header_cfgs(bindings_version(&bindings_path).as_deref(), use_version);
+
+                return;
+            },
+            Err(e) => panic!("{}", e),
+        }
+    };
+
+    // for dependent crates and C shims, as DEP_FUSE3_INCLUDE and DEP_FUSE3_ROOT
+    println!("cargo:include={}", unwrap!(env::join_paths(&fuse.include_paths)).to_string_lossy());
+
+    if let Some(root) = &fuse.root {
+        println!("cargo:root={}", root.display());
+    }
+
+    if !version_at_least(&fuse.version, min_version) {
+        fail(&bindings_path, &format!(
+            "the `{}` feature needs libfuse {} or newer, but pkg-config found {} {}",
+            feature, min_version, package, fuse.version));
+
+        return;
+    }
+
+    header_cfgs(Some(&fuse.version), use_version);
+
+    if pregenerated {
+        copy_shipped(&shipped_path, &bindings_path);
+
+        return;
+    }
+
+    generate_bindings(&fuse, &out_path, use_version, &bindings_path);
+
+    if env::var_os("LIBFUSE_SYS_UPDATE_PREGENERATED").is_some() {
+        unwrap!(std::fs::create_dir_all(unwrap!(shipped_path.parent())));
+        unwrap!(std::fs::copy(&bindings_path, &shipped_path));
+    }
+
+    // lets the tests compare both
+    if shipped_path.exists() {
+        println!("cargo:rustc-cfg=generated_bindings");
+        println!("cargo:rustc-env=LIBFUSE_SYS_SHIPPED_BINDINGS={}", shipped_path.display());
+    }
+}
+
+// the headers of the libfuse built against and the release they are from
+struct Fuse {
+    version: String,
+    include_paths: Vec<PathBuf>,
+    root: Option<PathBuf>,
+}
+
+// builds the static library from the sources meson would use on Linux,
+// with the headers and a `libfuse_config.h` as installed in `include/`
+#[cfg(feature = "vendored")]
+fn build_vendored(out: &Path) -> Fuse {
+    println!("cargo:rerun-if-env-changed=LIBFUSE_SYS_VENDORED_SOURCE");
+    println!("cargo:rerun-if-env-changed=LIBFUSE_SYS_FUSERMOUNT_DIR");
+
+    let source = match env::var_os("LIBFUSE_SYS_VENDORED_SOURCE") {
+        Some(source) => PathBuf::from(source),
+        None => PathBuf::from(unwrap!(env::var("CARGO_MANIFEST_DIR"))).join("libfuse"),
+    };
+
+    if !source.join("lib").join("fuse.c").exists() {
+        panic!(
+            "no libfuse sources in {}, put the ones of libfuse {} there \
+                or point LIBFUSE_SYS_VENDORED_SOURCE at them",
+            source.display(), VENDORED_VERSION);
+    }
+
+    println!("cargo:rerun-if-changed={}", source.display());
+
+    let include = out.join("include");
+    let config = out.join("config");
+
+    unwrap!(std::fs::create_dir_all(&include));
+    unwrap!(std::fs::create_dir_all(&config));
+
+    for header in files_with_extension(&source.join("include"), "h") {
+        unwrap!(std::fs::copy(&header, include.join(unwrap!(header.file_name()))));
+    }
+
+    // the static library keeps the symbol versions of the shared one
+    unwrap!(std::fs::write(
+        include.join("libfuse_config.h"),
+        "#define LIBFUSE_BUILT_WITH_VERSIONED_SYMBOLS 1\n"));
+
+    unwrap!(std::fs::write(
+        config.join("fuse_config.h"),
+        format!("#define PACKAGE_VERSION \"{}\"\n{}", VENDORED_VERSION, VENDORED_CONFIG)));
+
+    // mounting as a user runs the fusermount3 of the target
+    let fusermount_dir = env::var("LIBFUSE_SYS_FUSERMOUNT_DIR")
+        .unwrap_or_else(|_| String::from("/usr/bin"));
+
+    let mut build = cc::Build::new();
+
+    build
+        .include(&include)
+        .include(&config)
+        .include(source.join("lib"))
+        .define("_REENTRANT", None)
+        .define("HAVE_LIBFUSE_PRIVATE_CONFIG_H", None)
+        .define("_FILE_OFFSET_BITS", "64")
+        .define("FUSE_USE_VERSION", "312")
+        .define("FUSERMOUNT_DIR", format!("\"{}\"", fusermount_dir).as_str())
+        .flag_if_supported("-fno-strict-aliasing")
+        .warnings(false);
+
+    let lib = source.join("lib");
+    let sources = files_with_extension(&lib, "c")
+        .chain(files_with_extension(&lib.join("modules"), "c"));
+
+    for file in sources {
+        // the BSD counterpart of mount.c
+        if file.file_name() != Some("mount_bsd.c".as_ref()) {
+            build.file(file);
+        }
+    }
+
+    build.compile("fuse3");
+    unversion_symbols(&out.join("libfuse3.a"));
 
+    println!("cargo:rustc-link-lib=pthread");
+    println!("cargo:rustc-link-lib=dl");
+
+    Fuse {
+        version: String::from(VENDORED_VERSION),
+        include_paths: vec![include],
+        root: Some(out.to_path_buf()),
+    }
+}
+
+// without the version script of the shared library, a shared object linking the archive
+// fails on the `.symver` names: the default `name@@FUSE_x` become plain `name`,
+// the compatibility `name@FUSE_x` of older releases are dropped
+#[cfg(feature = "vendored")]
+fn unversion_symbols(archive: &Path) {
+    let nm = env::var("NM").unwrap_or_else(|_| String::from("nm"));
+    let objcopy = env::var("OBJCOPY").unwrap_or_else(|_| String::from("objcopy"));
+
+    let output = unwrap!(Command::new(nm)
+        .args(["-g", "--defined-only", "--format=posix"])
+        .arg(archive)
+        .output());
+
+    assert!(output.status.success(), "nm failed on {}", archive.display());
+
+    let symbols = String::from_utf8_lossy(&output.stdout);
+    let defined: Vec<&str> = symbols
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?;
+
+            // the lines naming the archive members have no symbol type
+            fields.next().map(|_| name)
+        })
+        .collect();
+
+    let mut args = Vec::new();
+
+    for symbol in defined.iter().filter(|symbol| symbol.contains('@')) {
+        match symbol.split_once("@@") {
+            // a plain definition, as in `compat.c`, takes precedence
+            Some((name, _)) if !defined.contains(&name) => {
+                args.push(format!("--redefine-sym={}={}", symbol, name));
+            },
+            _ => args.push(format!("--strip-symbol={}", symbol)),
+        }
+    }
+
+    if args.is_empty() {
+        return;
+    }
+
+    let status = unwrap!(Command::new(objcopy).args(&args).arg(archive).status());
+
+    assert!(status.success(), "objcopy failed on {}", archive.display());
+}
+
+#[cfg(not(feature = "vendored"))]
+fn build_vendored(_: &Path) -> Fuse {
+    unreachable!("libfuse is only built with the `vendored` feature");
+}
+
+#[cfg(feature = "vendored")]
+fn files_with_extension(dir: &Path, extension: &str) -> impl Iterator<Item=PathBuf> {
+    let mut files: Vec<_> = unwrap!(std::fs::read_dir(dir))
+        .map(|entry| unwrap!(entry).path())
+        .filter(|path| path.extension() == Some(extension.as_ref()))
+        .collect();
+
+    // keeps the archive the same from one build to the next
+    files.sort();
+
+    files.into_iter()
+}
+
+#[cfg(feature = "bindgen")]
+fn generate_bindings(fuse: &Fuse, out: &Path, use_version: u32, path: &Path) {
     let fuse_header = unwrap!(find_fuse_header(&fuse.include_paths));
-    let fuse_header = unwrap!(fuse_header.to_str());
+    let lowlevel_header = fuse_header.with_file_name("fuse_lowlevel.h");
+    let cuse_header = fuse_header.with_file_name("cuse_lowlevel.h");
 
-    println!("cargo:rerun-if-changed={}", fuse_header);
+    // only the high-level API is used with libfuse 2
+    let headers = if cfg!(feature = "fuse2") {
+        vec![unwrap!(fuse_header.to_str())]
+    } else {
+        vec![
+            unwrap!(fuse_header.to_str()),
+            unwrap!(lowlevel_header.to_str()),
+            unwrap!(cuse_header.to_str()),
+        ]
+    };
 
-    let out_path = PathBuf::from(unwrap!(env::var("OUT_DIR")));
+    for header in &headers {
+        println!("cargo:rerun-if-changed={}", header);
+    }
 
-    let fuse_header = generate_fuse_header(&out_path, fuse_header);
+    let fuse_header = generate_fuse_header(out, use_version, &headers);
     let fuse_header = unwrap!(fuse_header.to_str());
 
-    // install rustfmt(rustup component add rustfmt) to get formated bindings
+    // install rustfmt(rustup component add rustfmt) to get formated bindings,
+    // only the libfuse API is kept, the libc types it uses come along with it
     let bindings = bindgen::Builder::default()
         .header(fuse_header)
+        .whitelist_type("(fuse|cuse)(_.*)?")
+        .whitelist_function("(fuse|cuse)_.*")
+        .whitelist_var("(FUSE|CUSE)_.*")
+        .derive_debug(true)
+        .impl_debug(true)
+        .derive_default(true)
+        .layout_tests(true)
         .generate()
         .expect("Unable to generate bindings");
 
     bindings
-        .write_to_file(out_path.join("bindings.rs"))
+        .write_to_file(path)
         .expect("Couldn't write bindings!");
 }
 
+#[cfg(not(feature = "bindgen"))]
+fn generate_bindings(_: &Fuse, _: &Path, _: u32, _: &Path) {
+    unreachable!("bindings are only generated with the `bindgen` feature");
+}
+
+// fails in the crate itself, rather than on whatever the headers lack
+fn fail(bindings: &Path, message: &str) {
+    unwrap!(std::fs::write(bindings, format!("compile_error!({:?});\n", message)));
+}
+
+fn shipped_bindings(use_version: u32) -> PathBuf {
+    let arch = unwrap!(env::var("CARGO_CFG_TARGET_ARCH"));
+
+    PathBuf::from(unwrap!(env::var("CARGO_MANIFEST_DIR")))
+        .join("bindings")
+        .join(arch)
+        .join(format!("fuse_{}.rs", use_version))
+}
+
+// the bindings pregenerated into `bindings/`, none are committed to the crate
+fn copy_shipped(shipped: &Path, path: &Path) {
+    if !shipped.exists() {
+        fail(path, &format!(
+            "no pregenerated bindings in {}, generate them where libfuse and libclang are \
+                installed by building with `bindgen` and LIBFUSE_SYS_UPDATE_PREGENERATED=1",
+            shipped.display()));
+
+        return;
+    }
+
+    unwrap!(std::fs::copy(shipped, path));
+}
+
+// what differs between the libfuse releases an API level accepts, from the one built against
+fn header_cfgs(version: Option<&str>, use_version: u32) {
+    let version = match version {
+        Some(x) => x,
+        None => return,
+    };
+
+    // from 3.12 on `fuse_session_loop_mt` is a macro below FUSE_USE_VERSION 312
+    if !cfg!(feature = "fuse2") && use_version < 312 && version_at_least(version, "3.12.0") {
+        println!("cargo:rustc-cfg=fuse_loop_mt_32");
+    }
+
+    // and `FUSE_MAKE_VERSION` takes the major version times 100 instead of 10
+    if version_at_least(version, "3.12.0") {
+        println!("cargo:rustc-cfg=fuse_make_version_100");
+    }
+}
+
+// the release of the headers pregenerated bindings come from, `None` if there are none
+fn bindings_version(bindings: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(bindings).ok()?;
+
+    let constant = |name: &str| -> Option<u32> {
+        let rest = &content[content.find(&format!("const {}", name))?..];
+
+        rest[rest.find('=')? + 1..rest.find(';')?].trim().parse().ok()
+    };
+
+    Some(format!("{}.{}", constant("FUSE_MAJOR_VERSION")?, constant("FUSE_MINOR_VERSION")?))
+}
+
+fn feature_enabled(feature: &str) -> bool {
+    env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase())).is_some()
+}
+
+fn version_at_least(version: &str, min: &str) -> bool {
+    let parse = |version: &str| -> Vec<u32> {
+        version
+            .split('.')
+            .map(|x| x.parse().unwrap_or(0))
+            .collect()
+    };
+
+    parse(version) >= parse(min)
+}
+
+#[cfg(feature = "bindgen")]
 fn find_fuse_header(paths: &Vec<PathBuf>) -> Option<PathBuf> {
     for path in paths {
         let header = path.join("fuse.h");
@@ -46,17 +437,20 @@ fn find_fuse_header(paths: &Vec<PathBuf>) -> Option<PathBuf> {
     None
 }
 
-fn generate_fuse_header(out: &PathBuf, fuse_header: &str) -> PathBuf {
-    let content = format!("\
-#define FUSE_USE_VERSION {}
-#include \"{}\"",
-        FUSE_USE_VERSION, fuse_header);
+#[cfg(feature = "bindgen")]
+fn generate_fuse_header(out: &Path, use_version: u32, headers: &[&str]) -> PathBuf {
+    // libfuse is built with 64 bit offsets, which the libfuse 2 headers insist on
+    let mut content = format!(
+        "#define _FILE_OFFSET_BITS 64\n#define FUSE_USE_VERSION {}\n",
+        use_version);
 
-    let path = out.join("fuse.h");
+    for header in headers {
+        content.push_str(&format!("#include \"{}\"\n", header));
+    }
 
-    let mut file = unwrap!(File::create(&path));
+    let path = out.join("fuse.h");
 
-    unwrap!(file.write_all(content.as_bytes()));
+    unwrap!(std::fs::write(&path, content));
 
     path
 }
@@ -0,0 +1,614 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{ Arc, Mutex };
+use std::task::{ self, Poll, Waker };
+use std::time::Duration;
+
+use libc::c_int;
+use libc::ENOSYS;
+
+use unwrap::unwrap;
+
+use crate::{ fuse, Neg, neg };
+use crate::{ Context, DirEntry, DirEntryPlus, Entry, FileAttr, Interrupt, LowLevelOperations };
+use crate::ReadDir;
+use crate::RenameFlags;
+use crate::{ ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty };
+use crate::ReplyEntry;
+use crate::{ ReplyOpen, ReplyReadlink, ReplyStatfs, ReplyWrite, ReplyXattr, Request, SetAttr };
+use crate::XattrFlags;
+
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output=T> + Send + 'a>>;
+
+
+// runs the futures of `AsyncOperations` on an executor,
+// any `Fn(BoxFuture<'static, ()>)` closure is one
+pub trait Spawn {
+    fn spawn(&self, future: BoxFuture<'static, ()>);
+}
+
+impl<F: Fn(BoxFuture<'static, ()>)> Spawn for F {
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        self(future)
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub struct TokioSpawn(pub tokio::runtime::Handle);
+
+#[cfg(feature = "tokio")]
+impl TokioSpawn {
+    // the runtime the caller runs in, panics outside of one
+    pub fn current() -> Self {
+        TokioSpawn(tokio::runtime::Handle::current())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Spawn for TokioSpawn {
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        drop(self.0.spawn(future));
+    }
+}
+
+
+macro_rules! async_method {
+    ( $method:ident -> $T:ty; $( $arg:ident : $A:ty ),* ) => {
+        fn $method<'a>(&'a self,
+            ctx: &'a Context,
+            $( $arg: $A, )*) -> BoxFuture<'a, Result<$T, Neg>>
+        {
+            Box::pin(async { Err(neg!(-ENOSYS)) })
+        }
+    };
+}
+
+// the low-level API with every request answered by a future,
+// `fi` is a copy of the file info, handed back by `open`, `opendir` and `create`
+#[allow(unused_variables)]
+#[allow(clippy::too_many_arguments)]
+pub trait AsyncOperations: Send + Sync + 'static {
+    // as `LowLevelOperations::init`, `readdirplus` is only called once it is wanted here
+    fn init(&self, conn: &mut fuse::fuse_conn_info) { }
+
+    async_method! { lookup -> Entry; parent: u64, name: &'a str }
+
+    fn forget(&self, ino: u64, nlookup: u64) { }
+
+    async_method! { getattr -> (FileAttr, Duration); ino: u64, fi: Option<fuse::fuse_file_info> }
+
+    async_method! { setattr -> (FileAttr, Duration);
+        ino: u64,
+        attr: SetAttr,
+        fi: Option<fuse::fuse_file_info>
+    }
+
+    async_method! { readlink -> String; ino: u64 }
+
+    async_method! { mknod -> Entry;
+        parent: u64,
+        name: &'a str,
+        mode: fuse::mode_t,
+        rdev: fuse::dev_t
+    }
+
+    async_method! { mkdir  -> Entry; parent: u64, name: &'a str, mode: fuse::mode_t }
+    async_method! { unlink -> ()   ; parent: u64, name: &'a str }
+    async_method! { rmdir  -> ()   ; parent: u64, name: &'a str }
+
+    async_method! { symlink -> Entry; link: &'a str, parent: u64, name: &'a str }
+
+    async_method! { rename -> ();
+        parent: u64,
+        name: &'a str,
+        newparent: u64,
+        newname: &'a str,
+        flags: RenameFlags
+    }
+
+    async_method! { link -> Entry; ino: u64, newparent: u64, newname: &'a str }
+
+    fn open<'a>(&'a self,
+        ctx: &'a Context,
+        ino: u64,
+        fi: fuse::fuse_file_info) -> BoxFuture<'a, Result<fuse::fuse_file_info, Neg>>
+    {
+        Box::pin(async move { Ok(fi) })
+    }
+
+    async_method! { read -> Vec<u8>;
+        ino: u64,
+        size: usize,
+        offset: fuse::off_t,
+        fi: fuse::fuse_file_info
+    }
+
+    async_method! { write -> usize;
+        ino: u64,
+        data: &'a [u8],
+        offset: fuse::off_t,
+        fi: fuse::fuse_file_info
+    }
+
+    async_method! { flush -> (); ino: u64, fi: fuse::fuse_file_info }
+
+    fn release<'a>(&'a self,
+        ctx: &'a Context,
+        ino: u64,
+        fi: fuse::fuse_file_info) -> BoxFuture<'a, Result<(), Neg>>
+    {
+        Box::pin(async { Ok(()) })
+    }
+
+    async_method! { fsync -> (); ino: u64, datasync: bool, fi: fuse::fuse_file_info }
+
+    fn opendir<'a>(&'a self,
+        ctx: &'a Context,
+        ino: u64,
+        fi: fuse::fuse_file_info) -> BoxFuture<'a, Result<fuse::fuse_file_info, Neg>>
+    {
+        Box::pin(async move { Ok(fi) })
+    }
+
+    // the entries after the first `offset` ones, as many as fit are sent
+    async_method! { readdir -> Vec<DirEntry>; ino: u64, offset: u64, fi: fuse::fuse_file_info }
+
+    async_method! { readdirplus -> Vec<DirEntryPlus>;
+        ino: u64,
+        offset: u64,
+        fi: fuse::fuse_file_info
+    }
+
+    fn releasedir<'a>(&'a self,
+        ctx: &'a Context,
+        ino: u64,
+        fi: fuse::fuse_file_info) -> BoxFuture<'a, Result<(), Neg>>
+    {
+        Box::pin(async { Ok(()) })
+    }
+
+    async_method! { fsyncdir -> (); ino: u64, datasync: bool, fi: fuse::fuse_file_info }
+    async_method! { statfs -> fuse::statvfs; ino: u64 }
+
+    async_method! { setxattr -> ();
+        ino: u64,
+        name: &'a str,
+        value: &'a [u8],
+        flags: XattrFlags
+    }
+
+    async_method! { getxattr    -> Vec<u8>    ; ino: u64, name: &'a str }
+    async_method! { listxattr   -> Vec<String>; ino: u64 }
+    async_method! { removexattr -> ()         ; ino: u64, name: &'a str }
+    async_method! { access      -> ()         ; ino: u64, mask: c_int }
+
+    async_method! { create -> (Entry, fuse::fuse_file_info);
+        parent: u64,
+        name: &'a str,
+        mode: fuse::mode_t,
+        fi: fuse::fuse_file_info
+    }
+}
+
+
+// serves `AsyncOperations` as `LowLevelOperations`,
+// each request is spawned and answered once its future resolves
+pub struct AsyncAdapter<T, S> {
+    ops: Arc<T>,
+    spawner: S,
+}
+
+impl<T: AsyncOperations, S: Spawn> AsyncAdapter<T, S> {
+    pub fn new(ops: T, spawner: S) -> Self {
+        Self { ops: Arc::new(ops), spawner }
+    }
+}
+
+// resolves with EINTR as soon as the request is interrupted, dropping `future`
+struct Interruptible<'a, T> {
+    future: BoxFuture<'a, Result<T, Neg>>,
+    interrupt: Interrupt,
+    waker: Option<Arc<Mutex<Waker>>>,
+}
+
+impl<'a, T> Future for Interruptible<'a, T> {
+    type Output = Result<T, Neg>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        this.interrupt.check()?;
+
+        if let Poll::Ready(x) = this.future.as_mut().poll(cx) {
+            return Poll::Ready(x);
+        }
+
+        match &this.waker {
+            Some(waker) => *unwrap!(waker.lock()) = cx.waker().clone(),
+            None => {
+                let waker = Arc::new(Mutex::new(cx.waker().clone()));
+                let wake = waker.clone();
+
+                this.interrupt.on_interrupt(move || unwrap!(wake.lock()).wake_by_ref());
+                this.waker = Some(waker);
+            },
+        }
+
+        // the interrupt may have woken the previous waker
+        this.interrupt.check()?;
+
+        Poll::Pending
+    }
+}
+
+// moves the listed owned arguments into a spawned future calling `$method`,
+// then answers `$reply` with `$ok` or the error, EINTR once interrupted
+macro_rules! spawn {
+    ( $self:ident, $req:ident, $reply:ident, $method:ident ( $( $arg:expr ),* );
+      $x:pat => $ok:expr ) => {
+        {
+            let ops = $self.ops.clone();
+            let ctx = $req.context();
+            let interrupt = $req.interrupt();
+
+            $self.spawner.spawn(Box::pin(async move {
+                let future = ops.$method(&ctx, $( $arg, )*);
+
+                match (Interruptible { future, interrupt, waker: None }).await {
+                    Ok($x) => $ok,
+                    Err(e) => $reply.error(e),
+                }
+            }));
+        }
+    };
+}
+
+impl<T: AsyncOperations, S: Spawn> LowLevelOperations for AsyncAdapter<T, S> {
+    fn init(&mut self, conn: &mut fuse::fuse_conn_info) {
+        self.ops.init(conn);
+    }
+
+    fn lookup(&mut self, req: &Request, parent: u64, name: &str, reply: ReplyEntry) {
+        let name = name.to_owned();
+
+        spawn!(self, req, reply, lookup(parent, &name); e => reply.entry(&e));
+    }
+
+    fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
+        self.ops.forget(ino, nlookup);
+    }
+
+    fn getattr(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: Option<&mut fuse::fuse_file_info>,
+        reply: ReplyAttr)
+    {
+        let fi = fi.map(|x| *x);
+
+        spawn!(self, req, reply, getattr(ino, fi); (attr, ttl) => reply.attr(&attr, ttl));
+    }
+
+    fn setattr(&mut self,
+        req: &Request,
+        ino: u64,
+        attr: SetAttr,
+        fi: Option<&mut fuse::fuse_file_info>,
+        reply: ReplyAttr)
+    {
+        let fi = fi.map(|x| *x);
+
+        spawn!(self, req, reply, setattr(ino, attr, fi); (attr, ttl) => reply.attr(&attr, ttl));
+    }
+
+    fn readlink(&mut self, req: &Request, ino: u64, reply: ReplyReadlink) {
+        spawn!(self, req, reply, readlink(ino); link => reply.link(&link));
+    }
+
+    fn mknod(&mut self,
+        req: &Request,
+        parent: u64,
+        name: &str,
+        mode: fuse::mode_t,
+        rdev: fuse::dev_t,
+        reply: ReplyEntry)
+    {
+        let name = name.to_owned();
+
+        spawn!(self, req, reply, mknod(parent, &name, mode, rdev); e => reply.entry(&e));
+    }
+
+    fn mkdir(&mut self,
+        req: &Request,
+        parent: u64,
+        name: &str,
+        mode: fuse::mode_t,
+        reply: ReplyEntry)
+    {
+        let name = name.to_owned();
+
+        spawn!(self, req, reply, mkdir(parent, &name, mode); e => reply.entry(&e));
+    }
+
+    fn unlink(&mut self, req: &Request, parent: u64, name: &str, reply: ReplyEmpty) {
+        let name = name.to_owned();
+
+        spawn!(self, req, reply, unlink(parent, &name); () => reply.ok());
+    }
+
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &str, reply: ReplyEmpty) {
+        let name = name.to_owned();
+
+        spawn!(self, req, reply, rmdir(parent, &name); () => reply.ok());
+    }
+
+    fn symlink(&mut self,
+        req: &Request,
+        link: &str,
+        parent: u64,
+        name: &str,
+        reply: ReplyEntry)
+    {
+        let link = link.to_owned();
+        let name = name.to_owned();
+
+        spawn!(self, req, reply, symlink(&link, parent, &name); e => reply.entry(&e));
+    }
+
+    fn rename(&mut self,
+        req: &Request,
+        parent: u64,
+        name: &str,
+        newparent: u64,
+        newname: &str,
+        flags: RenameFlags,
+        reply: ReplyEmpty)
+    {
+        let name = name.to_owned();
+        let newname = newname.to_owned();
+
+        spawn!(self, req, reply, rename(parent, &name, newparent, &newname, flags); () => {
+            reply.ok()
+        });
+    }
+
+    fn link(&mut self,
+        req: &Request,
+        ino: u64,
+        newparent: u64,
+        newname: &str,
+        reply: ReplyEntry)
+    {
+        let newname = newname.to_owned();
+
+        spawn!(self, req, reply, link(ino, newparent, &newname); e => reply.entry(&e));
+    }
+
+    fn open(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyOpen)
+    {
+        let fi = *fi;
+
+        spawn!(self, req, reply, open(ino, fi); fi => reply.opened(&fi));
+    }
+
+    fn read(&mut self,
+        req: &Request,
+        ino: u64,
+        size: usize,
+        offset: fuse::off_t,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyData)
+    {
+        let fi = *fi;
+
+        spawn!(self, req, reply, read(ino, size, offset, fi); data => {
+            reply.data(&data[..data.len().min(size)])
+        });
+    }
+
+    fn write(&mut self,
+        req: &Request,
+        ino: u64,
+        data: &[u8],
+        offset: fuse::off_t,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyWrite)
+    {
+        let data = data.to_vec();
+        let fi = *fi;
+
+        spawn!(self, req, reply, write(ino, &data, offset, fi); n => reply.written(n));
+    }
+
+    fn flush(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyEmpty)
+    {
+        let fi = *fi;
+
+        spawn!(self, req, reply, flush(ino, fi); () => reply.ok());
+    }
+
+    fn release(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyEmpty)
+    {
+        let fi = *fi;
+
+        spawn!(self, req, reply, release(ino, fi); () => reply.ok());
+    }
+
+    fn fsync(&mut self,
+        req: &Request,
+        ino: u64,
+        datasync: bool,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyEmpty)
+    {
+        let fi = *fi;
+
+        spawn!(self, req, reply, fsync(ino, datasync, fi); () => reply.ok());
+    }
+
+    fn opendir(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyOpen)
+    {
+        let fi = *fi;
+
+        spawn!(self, req, reply, opendir(ino, fi); fi => reply.opened(&fi));
+    }
+
+    fn readdir(&mut self,
+        req: &Request,
+        ino: u64,
+        offset: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyDirectory)
+    {
+        let fi = *fi;
+
+        spawn!(self, req, reply, readdir(ino, offset, fi); entries => {
+            reply.fill(ReadDir::resume(entries))
+        });
+    }
+
+    fn readdirplus(&mut self,
+        req: &Request,
+        ino: u64,
+        offset: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyDirectoryPlus)
+    {
+        let fi = *fi;
+
+        spawn!(self, req, reply, readdirplus(ino, offset, fi); entries => {
+            reply.fill(ReadDir::resume(entries))
+        });
+    }
+
+    fn releasedir(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyEmpty)
+    {
+        let fi = *fi;
+
+        spawn!(self, req, reply, releasedir(ino, fi); () => reply.ok());
+    }
+
+    fn fsyncdir(&mut self,
+        req: &Request,
+        ino: u64,
+        datasync: bool,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyEmpty)
+    {
+        let fi = *fi;
+
+        spawn!(self, req, reply, fsyncdir(ino, datasync, fi); () => reply.ok());
+    }
+
+    fn statfs(&mut self, req: &Request, ino: u64, reply: ReplyStatfs) {
+        spawn!(self, req, reply, statfs(ino); stbuf => reply.statfs(&stbuf));
+    }
+
+    fn setxattr(&mut self,
+        req: &Request,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: XattrFlags,
+        reply: ReplyEmpty)
+    {
+        let name = name.to_owned();
+        let value = value.to_vec();
+
+        spawn!(self, req, reply, setxattr(ino, &name, &value, flags); () => reply.ok());
+    }
+
+    fn getxattr(&mut self, req: &Request, ino: u64, name: &str, reply: ReplyXattr) {
+        let name = name.to_owned();
+
+        spawn!(self, req, reply, getxattr(ino, &name); value => reply.value(&value));
+    }
+
+    fn listxattr(&mut self, req: &Request, ino: u64, reply: ReplyXattr) {
+        spawn!(self, req, reply, listxattr(ino); names => reply.names(&names));
+    }
+
+    fn removexattr(&mut self, req: &Request, ino: u64, name: &str, reply: ReplyEmpty) {
+        let name = name.to_owned();
+
+        spawn!(self, req, reply, removexattr(ino, &name); () => reply.ok());
+    }
+
+    fn access(&mut self, req: &Request, ino: u64, mask: c_int, reply: ReplyEmpty) {
+        spawn!(self, req, reply, access(ino, mask); () => reply.ok());
+    }
+
+    fn create(&mut self,
+        req: &Request,
+        parent: u64,
+        name: &str,
+        mode: fuse::mode_t,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyCreate)
+    {
+        let name = name.to_owned();
+        let fi = *fi;
+
+        spawn!(self, req, reply, create(parent, &name, mode, fi); (e, fi) => {
+            reply.created(&e, &fi)
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{ AtomicBool, Ordering };
+    use std::task::Wake;
+
+    use libc::EINTR;
+
+    use super::*;
+
+    struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn interrupt_wakes_and_fails() {
+        let interrupt = Interrupt::new();
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = task::Context::from_waker(&waker);
+
+        let future: BoxFuture<Result<(), Neg>> = Box::pin(std::future::pending());
+        let mut future = Interruptible { future, interrupt: interrupt.clone(), waker: None };
+
+        assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+
+        interrupt.interrupt();
+
+        assert!(flag.0.load(Ordering::SeqCst));
+        assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Ready(Err(neg!(-EINTR))));
+    }
+}
@@ -0,0 +1,130 @@
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+use libc::{ S_IFMT, S_IFREG, S_IFDIR, S_IFLNK, S_IFBLK, S_IFCHR, S_IFIFO, S_IFSOCK };
+
+use crate::fuse;
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum FileType {
+    RegularFile,
+    Directory,
+    Symlink,
+    BlockDevice,
+    CharDevice,
+    NamedPipe,
+    Socket,
+}
+
+impl FileType {
+    pub fn from_mode(mode: fuse::mode_t) -> Option<Self> {
+        match mode & S_IFMT {
+            S_IFREG => Some(FileType::RegularFile),
+            S_IFDIR => Some(FileType::Directory),
+            S_IFLNK => Some(FileType::Symlink),
+            S_IFBLK => Some(FileType::BlockDevice),
+            S_IFCHR => Some(FileType::CharDevice),
+            S_IFIFO => Some(FileType::NamedPipe),
+            S_IFSOCK => Some(FileType::Socket),
+            _ => None,
+        }
+    }
+
+    pub fn mode(self) -> fuse::mode_t {
+        match self {
+            FileType::RegularFile => S_IFREG,
+            FileType::Directory => S_IFDIR,
+            FileType::Symlink => S_IFLNK,
+            FileType::BlockDevice => S_IFBLK,
+            FileType::CharDevice => S_IFCHR,
+            FileType::NamedPipe => S_IFIFO,
+            FileType::Socket => S_IFSOCK,
+        }
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FileAttr {
+    pub ino: u64,
+    pub size: u64,
+    pub blocks: u64,
+    pub atime: SystemTime,
+    pub mtime: SystemTime,
+    pub ctime: SystemTime,
+    pub kind: FileType,
+    pub perm: u16,
+    pub nlink: u32,
+    pub uid: fuse::uid_t,
+    pub gid: fuse::gid_t,
+    pub rdev: fuse::dev_t,
+    pub blksize: u32,
+}
+
+impl FileAttr {
+    pub fn from_stat(stbuf: &fuse::stat) -> Option<Self> {
+        Some(Self {
+            ino: stbuf.st_ino as _,
+            size: stbuf.st_size as _,
+            blocks: stbuf.st_blocks as _,
+            atime: system_time(&stbuf.st_atim),
+            mtime: system_time(&stbuf.st_mtim),
+            ctime: system_time(&stbuf.st_ctim),
+            kind: FileType::from_mode(stbuf.st_mode)?,
+            perm: (stbuf.st_mode & !S_IFMT) as _,
+            nlink: stbuf.st_nlink as _,
+            uid: stbuf.st_uid,
+            gid: stbuf.st_gid,
+            rdev: stbuf.st_rdev,
+            blksize: stbuf.st_blksize as _,
+        })
+    }
+
+    pub fn mode(&self) -> fuse::mode_t {
+        self.kind.mode() | fuse::mode_t::from(self.perm)
+    }
+
+    pub fn fill(&self, stbuf: &mut fuse::stat) {
+        stbuf.clear();
+
+        stbuf.st_ino = self.ino as _;
+        stbuf.st_size = self.size as _;
+        stbuf.st_blocks = self.blocks as _;
+        stbuf.st_atim = timespec(self.atime);
+        stbuf.st_mtim = timespec(self.mtime);
+        stbuf.st_ctim = timespec(self.ctime);
+        stbuf.st_mode = self.mode();
+        stbuf.st_nlink = self.nlink as _;
+        stbuf.st_uid = self.uid;
+        stbuf.st_gid = self.gid;
+        stbuf.st_rdev = self.rdev;
+        stbuf.st_blksize = self.blksize as _;
+    }
+}
+
+
+pub(crate) fn system_time(ts: &fuse::timespec) -> SystemTime {
+    if ts.tv_sec >= 0 {
+        UNIX_EPOCH + Duration::new(ts.tv_sec as _, ts.tv_nsec as _)
+    } else {
+        UNIX_EPOCH - Duration::from_secs((-ts.tv_sec) as _) + Duration::from_nanos(ts.tv_nsec as _)
+    }
+}
+
+pub(crate) fn timespec(time: SystemTime) -> fuse::timespec {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => fuse::timespec {
+            tv_sec: d.as_secs() as _,
+            tv_nsec: d.subsec_nanos() as _,
+        },
+        Err(e) => {
+            let d = e.duration();
+            let secs = -(d.as_secs() as i64);
+
+            match d.subsec_nanos() {
+                0 => fuse::timespec { tv_sec: secs as _, tv_nsec: 0 },
+                n => fuse::timespec { tv_sec: (secs - 1) as _, tv_nsec: (1_000_000_000 - n) as _ },
+            }
+        },
+    }
+}
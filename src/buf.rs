@@ -0,0 +1,220 @@
+use std::convert::{ TryFrom, TryInto };
+use std::os::unix::io::RawFd;
+
+use libc::{ c_int, ENOMEM };
+
+use unwrap::unwrap;
+
+use crate::{ fuse, Neg, neg };
+
+
+#[derive(Clone, Debug)]
+pub enum Buf {
+    Mem(Vec<u8>),
+    // `len` bytes read from `fd` at `pos`, or at its current position when `pos` is `None`
+    Fd { fd: RawFd, pos: Option<fuse::off_t>, len: usize },
+}
+
+impl Buf {
+    pub fn len(&self) -> usize {
+        match self {
+            Buf::Mem(x) => x.len(),
+            Buf::Fd { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+
+#[derive(Copy, Clone, Debug)]
+pub enum BufRef<'a> {
+    Mem(&'a [u8]),
+    Fd { fd: RawFd, pos: Option<fuse::off_t>, len: usize },
+}
+
+
+pub struct WriteBuf<'a> {
+    bufv: &'a mut fuse::fuse_bufvec,
+}
+
+impl<'a> WriteBuf<'a> {
+    pub(crate) fn new(bufv: &'a mut fuse::fuse_bufvec) -> Self {
+        Self { bufv }
+    }
+
+    // `fuse_buf_size` would count the buffers already copied before `idx` too
+    pub fn len(&self) -> usize {
+        let rest: usize = self.raw_bufs().iter().skip(self.bufv.idx).map(|buf| buf.size).sum();
+
+        rest.saturating_sub(self.bufv.off)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn bufs(&self) -> Vec<BufRef<'_>> {
+        self.raw_bufs()
+            .iter()
+            .enumerate()
+            .skip(self.bufv.idx)
+            .map(|(i, buf)| {
+                let skip = if i == self.bufv.idx { self.bufv.off } else { 0 };
+                let len = buf.size - skip;
+
+                if buf.flags & fuse::fuse_buf_flags_FUSE_BUF_IS_FD == 0 {
+                    let mem = unsafe { std::slice::from_raw_parts(buf.mem.cast::<u8>(), buf.size) };
+
+                    BufRef::Mem(&mem[skip..])
+                } else if buf.flags & fuse::fuse_buf_flags_FUSE_BUF_FD_SEEK != 0 {
+                    let pos = buf.pos + unwrap!(fuse::off_t::try_from(skip));
+
+                    BufRef::Fd { fd: buf.fd, pos: Some(pos), len }
+                } else {
+                    BufRef::Fd { fd: buf.fd, pos: None, len }
+                }
+            })
+            .collect()
+    }
+
+    // moves the data into `fd` at `pos`, by splicing where possible
+    pub fn copy_to_fd(&mut self, fd: RawFd, pos: fuse::off_t) -> Result<usize, Neg> {
+        let mut dst = bufvec(fuse::fuse_buf {
+            size: self.len(),
+            flags: fuse::fuse_buf_flags_FUSE_BUF_IS_FD | fuse::fuse_buf_flags_FUSE_BUF_FD_SEEK,
+            mem: std::ptr::null_mut(),
+            fd,
+            pos,
+        });
+
+        self.copy(&mut dst)
+    }
+
+    pub fn to_vec(&mut self) -> Result<Vec<u8>, Neg> {
+        let mut data = vec![0u8; self.len()];
+
+        let mut dst = bufvec(fuse::fuse_buf {
+            size: data.len(),
+            flags: 0,
+            mem: data.as_mut_ptr().cast(),
+            fd: -1,
+            pos: 0,
+        });
+
+        let len = self.copy(&mut dst)?;
+        data.truncate(len);
+
+        Ok(data)
+    }
+
+    fn raw_bufs(&self) -> &[fuse::fuse_buf] {
+        unsafe { std::slice::from_raw_parts(self.bufv.buf.as_ptr(), self.bufv.count) }
+    }
+
+    fn copy(&mut self, dst: &mut fuse::fuse_bufvec) -> Result<usize, Neg> {
+        let res = unsafe { fuse::fuse_buf_copy(dst, self.bufv, 0) };
+
+        if res < 0 {
+            Err(neg!(unwrap!(c_int::try_from(res))))
+        } else {
+            Ok(unwrap!(res.try_into()))
+        }
+    }
+}
+
+
+fn bufvec(buf: fuse::fuse_buf) -> fuse::fuse_bufvec {
+    fuse::fuse_bufvec { count: 1, idx: 0, off: 0, buf: [buf] }
+}
+
+// allocates the bufvec handed to libfuse, which frees it and its memory buffers with `free`
+pub(crate) unsafe fn into_raw(bufs: Vec<Buf>) -> Result<*mut fuse::fuse_bufvec, Neg> {
+    let count = bufs.len().max(1);
+
+    let size = std::mem::size_of::<fuse::fuse_bufvec>()
+        + (count - 1) * std::mem::size_of::<fuse::fuse_buf>();
+
+    let bufv = libc::calloc(1, size) as *mut fuse::fuse_bufvec;
+
+    if bufv.is_null() {
+        return Err(neg!(-ENOMEM));
+    }
+
+    (*bufv).count = count;
+
+    let dst = std::slice::from_raw_parts_mut((*bufv).buf.as_mut_ptr(), count);
+
+    for (dst, buf) in dst.iter_mut().zip(bufs) {
+        *dst = match buf {
+            Buf::Mem(data) => {
+                let mem = libc::malloc(data.len().max(1));
+
+                if mem.is_null() {
+                    free(bufv);
+                    return Err(neg!(-ENOMEM));
+                }
+
+                mem.cast::<u8>().copy_from_nonoverlapping(data.as_ptr(), data.len());
+
+                fuse::fuse_buf { size: data.len(), flags: 0, mem, fd: -1, pos: 0 }
+            },
+            Buf::Fd { fd, pos, len } => fuse::fuse_buf {
+                size: len,
+                flags: match pos {
+                    Some(_) => {
+                        fuse::fuse_buf_flags_FUSE_BUF_IS_FD | fuse::fuse_buf_flags_FUSE_BUF_FD_SEEK
+                    },
+                    None => fuse::fuse_buf_flags_FUSE_BUF_IS_FD,
+                },
+                mem: std::ptr::null_mut(),
+                fd,
+                pos: pos.unwrap_or(0),
+            },
+        };
+    }
+
+    Ok(bufv)
+}
+
+unsafe fn free(bufv: *mut fuse::fuse_bufvec) {
+    let bufs = std::slice::from_raw_parts((*bufv).buf.as_ptr(), (*bufv).count);
+
+    for buf in bufs {
+        if buf.flags & fuse::fuse_buf_flags_FUSE_BUF_IS_FD == 0 {
+            libc::free(buf.mem);
+        }
+    }
+
+    libc::free(bufv.cast());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn len_after_idx_and_off() {
+        let mut data = vec![0u8; 10];
+
+        let mut bufv = bufvec(fuse::fuse_buf {
+            size: data.len(),
+            flags: 0,
+            mem: data.as_mut_ptr().cast(),
+            fd: -1,
+            pos: 0,
+        });
+
+        bufv.off = 3;
+        assert_eq!(WriteBuf::new(&mut bufv).len(), 7);
+
+        // all copied
+        bufv.idx = 1;
+        bufv.off = 0;
+        assert_eq!(WriteBuf::new(&mut bufv).len(), 0);
+        assert!(WriteBuf::new(&mut bufv).bufs().is_empty());
+    }
+}
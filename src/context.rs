@@ -0,0 +1,93 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use libc::EIO;
+
+use crate::{ fuse, Neg, neg };
+
+
+// each `Context` holds its own reference, the data outlives `fuse_main_with_data` if need be
+pub(crate) type PrivateData = Arc<dyn Any + Send + Sync>;
+
+#[derive(Clone, Debug)]
+pub struct Context {
+    pub uid: fuse::uid_t,
+    pub gid: fuse::gid_t,
+    pub pid: fuse::pid_t,
+    pub umask: fuse::mode_t,
+    pub(crate) private_data: Option<PrivateData>,
+}
+
+impl Context {
+    // returns `None` when called outside of a filesystem operation
+    pub fn current() -> Option<Self> {
+        let ctx = unsafe { fuse::fuse_get_context().as_ref()? };
+        let data = ctx.private_data as *const PrivateData;
+
+        Some(Self {
+            uid: ctx.uid,
+            gid: ctx.gid,
+            pid: ctx.pid,
+            umask: ctx.umask,
+            private_data: unsafe { data.as_ref().cloned() },
+        })
+    }
+
+    // the value given to `fuse_main_with_data`
+    pub fn private_data<T: Any>(&self) -> Option<&T> {
+        self.private_data.as_ref()?.downcast_ref()
+    }
+
+    // supplementary groups of the caller, read from its /proc entry like `fuse_getgroups` does,
+    // fails with ENOENT once the caller exited
+    pub fn groups(&self) -> Result<Vec<fuse::gid_t>, Neg> {
+        let path = format!("/proc/{0}/task/{0}/status", self.pid);
+
+        let status = match std::fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(e) => return Err(neg!(-e.raw_os_error().unwrap_or(EIO))),
+        };
+
+        let groups = match status.lines().find_map(|line| line.strip_prefix("Groups:")) {
+            Some(x) => x,
+            None => return Err(neg!(-EIO)),
+        };
+
+        groups
+            .split_whitespace()
+            .map(|gid| gid.parse().map_err(|_| neg!(-EIO)))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use unwrap::unwrap;
+
+    use super::*;
+
+    #[test]
+    fn groups_of_this_process() {
+        let ctx = Context {
+            uid: 0,
+            gid: 0,
+            pid: unwrap!(std::process::id().try_into()),
+            umask: 0,
+            private_data: None,
+        };
+
+        let mut expected = vec![0; 1024];
+        let n = unsafe { libc::getgroups(1024, expected.as_mut_ptr()) };
+        expected.truncate(unwrap!(n.try_into()));
+
+        let mut groups = unwrap!(ctx.groups());
+
+        groups.sort();
+        expected.sort();
+
+        assert_eq!(groups, expected);
+    }
+}
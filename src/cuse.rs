@@ -0,0 +1,259 @@
+use std::ffi::CString;
+use std::convert::TryInto;
+use std::sync::{ Mutex, MutexGuard };
+
+use libc::{ c_int, c_char, c_uint, c_void };
+use libc::{ ENOSYS, ENOTTY };
+
+use unwrap::unwrap;
+
+use crate::{ fuse, Neg, neg, IoctlCmd, IoctlFlags, PollHandle, Request };
+use crate::{ ReplyData, ReplyEmpty, ReplyIoctl, ReplyOpen, ReplyPoll, ReplyWrite };
+
+
+// the character device created by `cuse_main`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeviceInfo {
+    // the device shows up as `/dev/<name>`
+    pub name: String,
+    // a `major` of 0 lets the kernel pick the device number
+    pub major: u32,
+    pub minor: u32,
+    // lets `ioctl` ask for caller memory through `ReplyIoctl::retry`
+    pub unrestricted_ioctl: bool,
+}
+
+impl DeviceInfo {
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        Self { name: name.into(), major: 0, minor: 0, unrestricted_ioctl: false }
+    }
+}
+
+
+// a character device of `cuse_lowlevel.h`, the calls are serialized,
+// answer the replies from another thread to not hold up the other requests
+#[allow(unused_variables)]
+#[allow(clippy::too_many_arguments)]
+pub trait CharDevice {
+    fn init(&mut self, conn: &mut fuse::fuse_conn_info) { }
+    // the device node exists from now on
+    fn init_done(&mut self) { }
+    fn destroy(&mut self) { }
+
+    fn open(&mut self, req: &Request, fi: &mut fuse::fuse_file_info, reply: ReplyOpen) {
+        reply.opened(fi);
+    }
+
+    fn read(&mut self,
+        req: &Request,
+        size: usize,
+        offset: fuse::off_t,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyData) { reply.error(neg!(-ENOSYS)); }
+
+    fn write(&mut self,
+        req: &Request,
+        data: &[u8],
+        offset: fuse::off_t,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyWrite) { reply.error(neg!(-ENOSYS)); }
+
+    fn flush(&mut self, req: &Request, fi: &mut fuse::fuse_file_info, reply: ReplyEmpty) {
+        reply.error(neg!(-ENOSYS));
+    }
+
+    fn release(&mut self, req: &Request, fi: &mut fuse::fuse_file_info, reply: ReplyEmpty) {
+        reply.ok();
+    }
+
+    fn fsync(&mut self,
+        req: &Request,
+        datasync: bool,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyEmpty) { reply.error(neg!(-ENOSYS)); }
+
+    // `input` is what the caller passed in for `IoctlDir::WRITE` commands,
+    // up to `out_size` bytes are copied back for `IoctlDir::READ` ones
+    fn ioctl(&mut self,
+        req: &Request,
+        cmd: IoctlCmd,
+        arg: usize,
+        fi: &mut fuse::fuse_file_info,
+        flags: IoctlFlags,
+        input: &[u8],
+        out_size: usize,
+        reply: ReplyIoctl) { reply.error(neg!(-ENOTTY)); }
+
+    // without an implementation the device is always ready
+    fn poll(&mut self,
+        req: &Request,
+        fi: &mut fuse::fuse_file_info,
+        ph: Option<PollHandle>,
+        reply: ReplyPoll) { reply.error(neg!(-ENOSYS)); }
+}
+
+
+// creates the device and serves it until it is removed or interrupted,
+// `args` start with the program name and take `-f`, `-d` and `-s` like `fuse_main`
+pub fn cuse_main<T, U>(args: T, info: &DeviceInfo, dev: U) -> Result<(), i32>
+    where T: Iterator<Item=String>,
+          U: 'static + Send + CharDevice
+{
+    let mut c_args: Vec<_> = args
+        .map(|arg| unwrap!(CString::new(arg)).into_raw())
+        .collect();
+
+    let dev_name = unwrap!(CString::new(format!("DEVNAME={}", info.name)));
+    let mut dev_info_argv = [dev_name.as_ptr()];
+
+    let ci = fuse::cuse_info {
+        dev_major: info.major,
+        dev_minor: info.minor,
+        dev_info_argc: unwrap!(dev_info_argv.len().try_into()),
+        dev_info_argv: dev_info_argv.as_mut_ptr(),
+        flags: if info.unrestricted_ioctl { fuse::CUSE_UNRESTRICTED_IOCTL as c_uint } else { 0 },
+    };
+
+    let dev: *mut UserData = Box::into_raw(Box::new(Mutex::new(Box::new(dev))));
+    let clop = cuse_lowlevel_ops_new();
+
+    unsafe {
+        let err = fuse::cuse_lowlevel_main(
+            unwrap!(c_args.len().try_into()),
+            c_args.as_mut_ptr(),
+            &ci,
+            &clop,
+            dev.cast());
+
+        drop(Box::from_raw(dev));
+
+        for arg in c_args {
+            drop(CString::from_raw(arg));
+        }
+
+        if err == 0 {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+}
+
+
+// the command became unsigned with FUSE_USE_VERSION 35
+#[cfg(not(feature = "fuse_3_12"))]
+type RawCmd = c_int;
+#[cfg(feature = "fuse_3_12")]
+type RawCmd = c_uint;
+
+// `cuse_lowlevel_main` serves requests from several threads unless given `-s`
+type UserData = Mutex<Box<dyn CharDevice + Send>>;
+
+unsafe fn user_dev<'a>(userdata: *mut c_void) -> MutexGuard<'a, Box<dyn CharDevice + Send>> {
+    unwrap!(ptr_mut!(userdata.cast::<UserData>()).lock())
+}
+
+macro_rules! dev_op {
+    ( $req:ident, $method:ident, $( $arg:expr ),* ) => {
+        user_dev(fuse::fuse_req_userdata($req)).$method(&Request::new($req), $( $arg, )*)
+    };
+}
+
+unsafe extern "C" fn init(userdata: *mut c_void, conn: *mut fuse::fuse_conn_info) {
+    user_dev(userdata).init(ptr_mut!(conn));
+}
+
+unsafe extern "C" fn init_done(userdata: *mut c_void) {
+    user_dev(userdata).init_done();
+}
+
+unsafe extern "C" fn destroy(userdata: *mut c_void) {
+    user_dev(userdata).destroy();
+}
+
+unsafe extern "C" fn open(req: fuse::fuse_req_t, fi: *mut fuse::fuse_file_info) {
+    dev_op!(req, open, ptr_mut!(fi), ReplyOpen::new(req));
+}
+
+unsafe extern "C" fn read(
+    req: fuse::fuse_req_t,
+    size: usize,
+    off: fuse::off_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    dev_op!(req, read, size, off, ptr_mut!(fi), ReplyData::new(req));
+}
+
+unsafe extern "C" fn write(
+    req: fuse::fuse_req_t,
+    buf: *const c_char,
+    size: usize,
+    off: fuse::off_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    let data = std::slice::from_raw_parts(buf.cast(), size);
+
+    dev_op!(req, write, data, off, ptr_mut!(fi), ReplyWrite::new(req));
+}
+
+unsafe extern "C" fn flush(req: fuse::fuse_req_t, fi: *mut fuse::fuse_file_info) {
+    dev_op!(req, flush, ptr_mut!(fi), ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn release(req: fuse::fuse_req_t, fi: *mut fuse::fuse_file_info) {
+    dev_op!(req, release, ptr_mut!(fi), ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn fsync(req: fuse::fuse_req_t, datasync: c_int, fi: *mut fuse::fuse_file_info) {
+    dev_op!(req, fsync, datasync != 0, ptr_mut!(fi), ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn ioctl(
+    req: fuse::fuse_req_t,
+    cmd: RawCmd,
+    arg: *mut c_void,
+    fi: *mut fuse::fuse_file_info,
+    flags: c_uint,
+    in_buf: *const c_void,
+    in_bufsz: usize,
+    out_bufsz: usize)
+{
+    let input: &[u8] = if in_buf.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(in_buf.cast(), in_bufsz)
+    };
+
+    dev_op!(req, ioctl,
+        IoctlCmd(cmd as c_uint),
+        arg as usize,
+        ptr_mut!(fi),
+        IoctlFlags::from_bits_truncate(flags),
+        input,
+        out_bufsz,
+        ReplyIoctl::new(req));
+}
+
+unsafe extern "C" fn poll(
+    req: fuse::fuse_req_t,
+    fi: *mut fuse::fuse_file_info,
+    ph: *mut fuse::fuse_pollhandle)
+{
+    dev_op!(req, poll, ptr_mut!(fi), PollHandle::from_raw(ph), ReplyPoll::new(req));
+}
+
+fn cuse_lowlevel_ops_new() -> fuse::cuse_lowlevel_ops {
+    fuse::cuse_lowlevel_ops {
+        init: Some(init),
+        init_done: Some(init_done),
+        destroy: Some(destroy),
+        open: Some(open),
+        read: Some(read),
+        write: Some(write),
+        flush: Some(flush),
+        release: Some(release),
+        fsync: Some(fsync),
+        ioctl: Some(ioctl),
+        poll: Some(poll),
+    }
+}
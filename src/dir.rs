@@ -0,0 +1,143 @@
+use libc::{ c_char, c_void };
+
+use unwrap::unwrap;
+
+use crate::{ fuse, FileAttr, FileType };
+
+
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: FileType,
+    pub ino: u64,
+    pub attr: Option<FileAttr>,
+}
+
+impl DirEntry {
+    pub fn new<T: Into<String>>(name: T, kind: FileType, ino: u64) -> Self {
+        Self { name: name.into(), kind, ino, attr: None }
+    }
+
+    pub fn with_attr<T: Into<String>>(name: T, attr: FileAttr) -> Self {
+        Self { name: name.into(), kind: attr.kind, ino: attr.ino, attr: Some(attr) }
+    }
+
+    pub(crate) fn fill(&self, stbuf: &mut fuse::stat) {
+        if let Some(attr) = &self.attr {
+            attr.fill(stbuf);
+        } else {
+            stbuf.clear();
+        }
+
+        stbuf.st_ino = self.ino as _;
+        stbuf.st_mode = self.kind.mode() | (stbuf.st_mode & !libc::S_IFMT);
+    }
+}
+
+
+pub enum ReadDir<'a, E = DirEntry> {
+    // the whole directory from its first entry, libfuse keeps it and answers the following calls
+    Whole(Box<dyn Iterator<Item=E> + 'a>),
+    // the entries after the first `offset` ones,
+    // read again from the next offset once the buffer is full
+    Resume(Box<dyn Iterator<Item=E> + 'a>),
+}
+
+impl<'a, E: 'a> ReadDir<'a, E> {
+    pub fn whole<T>(entries: T) -> Self
+        where T: IntoIterator<Item=E>,
+              T::IntoIter: 'a
+    {
+        ReadDir::Whole(Box::new(entries.into_iter()))
+    }
+
+    pub fn resume<T>(entries: T) -> Self
+        where T: IntoIterator<Item=E>,
+              T::IntoIter: 'a
+    {
+        ReadDir::Resume(Box::new(entries.into_iter()))
+    }
+
+    // the entries after the first `offset` ones, each paired with its own offset
+    #[cfg(not(feature = "fuse2"))]
+    pub(crate) fn after(self, offset: u64) -> impl Iterator<Item=(u64, E)> + 'a {
+        let (entries, skip) = match self {
+            ReadDir::Whole(x) => (x, offset as usize),
+            ReadDir::Resume(x) => (x, 0),
+        };
+
+        (offset + 1..).zip(entries.skip(skip))
+    }
+}
+
+impl<'a> ReadDir<'a> {
+    pub(crate) unsafe fn fill(
+        self,
+        buf: *mut c_void,
+        filler: fuse::fuse_fill_dir_t,
+        offset: fuse::off_t,
+        plus: bool)
+    {
+        let (entries, mut next) = match self {
+            ReadDir::Whole(x) => (x, None),
+            ReadDir::Resume(x) => (x, Some(offset)),
+        };
+
+        let mut name = Vec::new();
+        let mut stbuf = std::mem::MaybeUninit::<fuse::stat>::zeroed().assume_init();
+
+        for entry in entries {
+            let off = match next.as_mut() {
+                Some(x) => {
+                    *x += 1;
+                    *x
+                },
+                None => 0,
+            };
+
+            // a name containing a nul byte can't be listed, its offset is still used up
+            if entry.name.as_bytes().contains(&0) {
+                continue;
+            }
+
+            name.clear();
+            name.extend_from_slice(entry.name.as_bytes());
+            name.push(0);
+
+            entry.fill(&mut stbuf);
+
+            // without attributes the kernel looks the entry up as usual
+            if add(filler, buf, name.as_ptr().cast(), &stbuf, off, plus && entry.attr.is_some()) {
+                break;
+            }
+        }
+    }
+}
+
+// `true` once the buffer is full
+#[cfg(not(feature = "fuse2"))]
+unsafe fn add(
+    filler: fuse::fuse_fill_dir_t,
+    buf: *mut c_void,
+    name: *const c_char,
+    stbuf: &fuse::stat,
+    off: fuse::off_t,
+    plus: bool) -> bool
+{
+    let flags = if plus { fuse::fuse_fill_dir_flags_FUSE_FILL_DIR_PLUS } else { 0 };
+
+    unwrap!(filler)(buf, name, stbuf, off, flags) != 0
+}
+
+// libfuse 2 has no readdirplus, the entries are looked up
+#[cfg(feature = "fuse2")]
+unsafe fn add(
+    filler: fuse::fuse_fill_dir_t,
+    buf: *mut c_void,
+    name: *const c_char,
+    stbuf: &fuse::stat,
+    off: fuse::off_t,
+    _plus: bool) -> bool
+{
+    unwrap!(filler)(buf, name, stbuf, off) != 0
+}
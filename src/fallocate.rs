@@ -0,0 +1,160 @@
+use bitflags::bitflags;
+
+use libc::c_int;
+use libc::{ EFBIG, EINVAL, EIO, EOPNOTSUPP };
+
+use crate::{ Neg, neg };
+
+
+static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+
+
+bitflags! {
+    pub struct FallocateFlags: c_int {
+        const KEEP_SIZE = 0x01;
+        const PUNCH_HOLE = 0x02;
+        const COLLAPSE_RANGE = 0x08;
+        const ZERO_RANGE = 0x10;
+        const INSERT_RANGE = 0x20;
+    }
+}
+
+
+// implements `Operations::fallocate` for a file of `size` bytes on a backend
+// that only has `write(data, offset)` and `truncate(size)`,
+// holes are punched and ranges zeroed by writing zeros
+pub fn emulate<W, T>(
+    mode: FallocateFlags,
+    offset: u64,
+    length: u64,
+    size: u64,
+    mut write: W,
+    mut truncate: T) -> Result<(), Neg>
+    where W: FnMut(&[u8], u64) -> Result<usize, Neg>,
+          T: FnMut(u64) -> Result<(), Neg>
+{
+    if length == 0 {
+        return Err(neg!(-EINVAL));
+    }
+
+    let end = offset.checked_add(length).ok_or(neg!(-EFBIG))?;
+
+    if mode.intersects(FallocateFlags::COLLAPSE_RANGE | FallocateFlags::INSERT_RANGE) {
+        return Err(neg!(-EOPNOTSUPP));
+    }
+
+    if mode.contains(FallocateFlags::PUNCH_HOLE) {
+        if !mode.contains(FallocateFlags::KEEP_SIZE) || mode.contains(FallocateFlags::ZERO_RANGE) {
+            return Err(neg!(-EINVAL));
+        }
+
+        return zero(offset, end.min(size), &mut write);
+    }
+
+    let keep_size = mode.contains(FallocateFlags::KEEP_SIZE);
+
+    if mode.contains(FallocateFlags::ZERO_RANGE) {
+        return zero(offset, if keep_size { end.min(size) } else { end }, &mut write);
+    }
+
+    if !keep_size && end > size {
+        truncate(end)?;
+    }
+
+    Ok(())
+}
+
+fn zero<W>(mut offset: u64, end: u64, write: &mut W) -> Result<(), Neg>
+    where W: FnMut(&[u8], u64) -> Result<usize, Neg>
+{
+    while offset < end {
+        let len = ZEROS.len().min((end - offset) as usize);
+
+        match write(&ZEROS[..len], offset)? {
+            0 => return Err(neg!(-EIO)),
+            n => offset += n as u64,
+        }
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    fn run(mode: FallocateFlags, offset: u64, length: u64, file: &RefCell<Vec<u8>>)
+        -> Result<(), Neg>
+    {
+        let size = file.borrow().len() as u64;
+
+        let write = |data: &[u8], offset: u64| {
+            let mut file = file.borrow_mut();
+            let (start, end) = (offset as usize, offset as usize + data.len());
+
+            if file.len() < end {
+                file.resize(end, 0);
+            }
+
+            file[start..end].copy_from_slice(data);
+
+            Ok(data.len())
+        };
+
+        let truncate = |size: u64| {
+            file.borrow_mut().resize(size as usize, 0);
+
+            Ok(())
+        };
+
+        emulate(mode, offset, length, size, write, truncate)
+    }
+
+    #[test]
+    fn punch_hole() {
+        let file = RefCell::new(vec![1u8; 10]);
+        let punch = FallocateFlags::PUNCH_HOLE;
+
+        assert_eq!(run(punch, 2, 4, &file), Err(neg!(-EINVAL)));
+        assert_eq!(*file.borrow(), vec![1u8; 10]);
+
+        assert_eq!(run(punch | FallocateFlags::KEEP_SIZE, 8, 4, &file), Ok(()));
+        assert_eq!(*file.borrow(), [1, 1, 1, 1, 1, 1, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn zero_range() {
+        let file = RefCell::new(vec![1u8; 4]);
+        let zero = FallocateFlags::ZERO_RANGE;
+
+        assert_eq!(run(zero | FallocateFlags::KEEP_SIZE, 2, 4, &file), Ok(()));
+        assert_eq!(*file.borrow(), [1, 1, 0, 0]);
+
+        assert_eq!(run(zero, 6, 2, &file), Ok(()));
+        assert_eq!(*file.borrow(), [1, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn allocate() {
+        let file = RefCell::new(vec![1u8; 4]);
+
+        assert_eq!(run(FallocateFlags::KEEP_SIZE, 0, 8, &file), Ok(()));
+        assert_eq!(file.borrow().len(), 4);
+
+        assert_eq!(run(FallocateFlags::empty(), 2, 6, &file), Ok(()));
+        assert_eq!(*file.borrow(), [1, 1, 1, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        let file = RefCell::new(vec![1u8; 4]);
+
+        assert_eq!(run(FallocateFlags::empty(), 0, 0, &file), Err(neg!(-EINVAL)));
+        assert_eq!(run(FallocateFlags::ZERO_RANGE, u64::MAX, 1, &file), Err(neg!(-EFBIG)));
+        assert_eq!(run(FallocateFlags::COLLAPSE_RANGE, 0, 2, &file), Err(neg!(-EOPNOTSUPP)));
+        assert_eq!(*file.borrow(), vec![1u8; 4]);
+    }
+}
@@ -1,6 +1,7 @@
 #[allow(non_upper_case_globals)]
 #[allow(non_camel_case_types)]
 #[allow(non_snake_case)]
+#[allow(deref_nullptr)]
 mod fuse_bindings {
     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 }
@@ -9,8 +10,47 @@ mod fuse_bindings {
 pub use fuse_bindings::*;
 
 
+// bindgen skips the function-like `FUSE_MAKE_VERSION` and the `FUSE_VERSION` built with it,
+// the headers of libfuse 3.12 and later take the major version times 100, older ones times 10
+#[cfg(fuse_make_version_100)]
+pub const fn fuse_make_version(major: u32, minor: u32) -> u32 {
+    major * 100 + minor
+}
+
+#[cfg(not(fuse_make_version_100))]
+pub const fn fuse_make_version(major: u32, minor: u32) -> u32 {
+    major * 10 + minor
+}
+
+// the version of the headers built against, as `fuse_version` returns the one loaded
+pub const FUSE_VERSION: u32 = fuse_make_version(FUSE_MAJOR_VERSION, FUSE_MINOR_VERSION);
+
+
+// libfuse 2 takes these settings as mount options, `Operations::init` gets an empty one
+#[cfg(feature = "fuse2")]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Default)]
+pub struct fuse_config {
+    _private: (),
+}
+
+
 impl stat {
     pub fn clear(&mut self) {
         unsafe { std::ptr::write_bytes(self, 0, 1); }
     }
 }
+
+
+#[cfg(all(test, generated_bindings))]
+mod tests {
+    // the shipped bindings of this target and API level are what bindgen gives here
+    #[test]
+    fn shipped_bindings_match_generated() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+        let shipped = include_str!(env!("LIBFUSE_SYS_SHIPPED_BINDINGS"));
+
+        // a plain assert, the diff of the whole bindings is of no use
+        assert!(generated == shipped, "outdated, regenerate with LIBFUSE_SYS_UPDATE_PREGENERATED");
+    }
+}
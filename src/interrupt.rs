@@ -0,0 +1,159 @@
+#[cfg(not(feature = "fuse2"))]
+use std::collections::HashMap;
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::{ Receiver, RecvTimeoutError };
+use std::time::Duration;
+
+#[cfg(not(feature = "fuse2"))]
+use libc::c_void;
+use libc::{ EINTR, EIO };
+
+use unwrap::unwrap;
+
+use crate::{ fuse, Neg, neg };
+
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// the tokens of the low-level requests not answered yet, by request
+#[cfg(not(feature = "fuse2"))]
+static REQUESTS: Mutex<Option<HashMap<usize, Interrupt>>> = Mutex::new(None);
+
+
+// whether the request handled by the current thread was interrupted,
+// requires the `intr` mount option with the high-level API
+pub fn interrupted() -> bool {
+    unsafe { fuse::fuse_interrupted() != 0 }
+}
+
+pub fn check_interrupted() -> Result<(), Neg> {
+    if interrupted() {
+        Err(neg!(-EINTR))
+    } else {
+        Ok(())
+    }
+}
+
+
+#[derive(Clone, Default)]
+pub struct Interrupt {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    interrupted: AtomicBool,
+    callbacks: Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+}
+
+impl Interrupt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the token of `req`, interrupted along with it until it is answered
+    #[cfg(not(feature = "fuse2"))]
+    pub(crate) fn of_request(req: fuse::fuse_req_t) -> Self {
+        let interrupt = {
+            let mut requests = unwrap!(REQUESTS.lock());
+            let requests = requests.get_or_insert_with(HashMap::new);
+
+            if let Some(x) = requests.get(&(req as usize)) {
+                return x.clone();
+            }
+
+            let interrupt = Self::new();
+
+            requests.insert(req as usize, interrupt.clone());
+
+            interrupt
+        };
+
+        // calls back right away if the request already is interrupted
+        unsafe {
+            fuse::fuse_req_interrupt_func(req, Some(request_interrupted), std::ptr::null_mut());
+        }
+
+        interrupt
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.inner.interrupted.load(Ordering::Acquire)
+    }
+
+    pub fn check(&self) -> Result<(), Neg> {
+        if self.is_interrupted() {
+            Err(neg!(-EINTR))
+        } else {
+            Ok(())
+        }
+    }
+
+    // `f` runs at most once, right away if the request is already interrupted
+    pub fn on_interrupt<F: 'static + FnOnce() + Send>(&self, f: F) {
+        let mut callbacks = unwrap!(self.inner.callbacks.lock());
+
+        if self.is_interrupted() {
+            drop(callbacks);
+            f();
+        } else {
+            callbacks.push(Box::new(f));
+        }
+    }
+
+    pub fn interrupt(&self) {
+        let callbacks = {
+            let mut callbacks = unwrap!(self.inner.callbacks.lock());
+
+            if self.inner.interrupted.swap(true, Ordering::AcqRel) {
+                return;
+            }
+
+            std::mem::take(&mut *callbacks)
+        };
+
+        for f in callbacks {
+            f();
+        }
+    }
+
+    // waits on the request thread for work handed to another thread,
+    // interrupting `self` and failing with EINTR if the request is interrupted
+    pub fn recv<T>(&self, rx: &Receiver<T>) -> Result<T, Neg> {
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(x) => return Ok(x),
+                Err(RecvTimeoutError::Disconnected) => return Err(neg!(-EIO)),
+                Err(RecvTimeoutError::Timeout) => {
+                    if interrupted() {
+                        self.interrupt();
+                    }
+
+                    self.check()?;
+                },
+            }
+        }
+    }
+}
+
+
+// drops the token of `req`, which is about to be answered
+#[cfg(not(feature = "fuse2"))]
+pub(crate) fn forget_request(req: fuse::fuse_req_t) {
+    if let Some(requests) = unwrap!(REQUESTS.lock()).as_mut() {
+        requests.remove(&(req as usize));
+    }
+}
+
+// libfuse calls it with `req` locked, once the kernel interrupts it
+#[cfg(not(feature = "fuse2"))]
+unsafe extern "C" fn request_interrupted(req: fuse::fuse_req_t, _: *mut c_void) {
+    let interrupt = unwrap!(REQUESTS.lock())
+        .as_ref()
+        .and_then(|requests| requests.get(&(req as usize)).cloned());
+
+    if let Some(interrupt) = interrupt {
+        interrupt.interrupt();
+    }
+}
@@ -0,0 +1,160 @@
+use bitflags::bitflags;
+
+use libc::c_uint;
+
+use crate::fuse;
+
+
+const NR_SHIFT: c_uint = 0;
+const TYPE_SHIFT: c_uint = 8;
+const SIZE_SHIFT: c_uint = 16;
+const DIR_SHIFT: c_uint = 30;
+
+const SIZE_MASK: c_uint = (1 << 14) - 1;
+
+
+bitflags! {
+    pub struct IoctlDir: c_uint {
+        const WRITE = 1;
+        const READ = 2;
+    }
+}
+
+bitflags! {
+    pub struct IoctlFlags: c_uint {
+        const COMPAT = fuse::FUSE_IOCTL_COMPAT as c_uint;
+        const UNRESTRICTED = fuse::FUSE_IOCTL_UNRESTRICTED as c_uint;
+        const RETRY = fuse::FUSE_IOCTL_RETRY as c_uint;
+        const DIR = fuse::FUSE_IOCTL_DIR as c_uint;
+    }
+}
+
+
+// an `_IOC` encoded command, declare a command set as consts and match on them:
+// `const GET_COUNT: IoctlCmd = IoctlCmd::read::<u32>(b'E', 1);`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct IoctlCmd(pub c_uint);
+
+impl IoctlCmd {
+    pub const fn new(dir: IoctlDir, kind: u8, nr: u8, size: usize) -> Self {
+        Self::encode(dir.bits(), kind, nr, size)
+    }
+
+    pub const fn none(kind: u8, nr: u8) -> Self {
+        Self::new(IoctlDir::empty(), kind, nr, 0)
+    }
+
+    pub const fn read<T>(kind: u8, nr: u8) -> Self {
+        Self::new(IoctlDir::READ, kind, nr, std::mem::size_of::<T>())
+    }
+
+    pub const fn write<T>(kind: u8, nr: u8) -> Self {
+        Self::new(IoctlDir::WRITE, kind, nr, std::mem::size_of::<T>())
+    }
+
+    pub const fn read_write<T>(kind: u8, nr: u8) -> Self {
+        let dir = IoctlDir::READ.bits() | IoctlDir::WRITE.bits();
+
+        Self::encode(dir, kind, nr, std::mem::size_of::<T>())
+    }
+
+    pub fn dir(self) -> IoctlDir {
+        IoctlDir::from_bits_truncate(self.0 >> DIR_SHIFT)
+    }
+
+    pub fn kind(self) -> u8 {
+        (self.0 >> TYPE_SHIFT) as u8
+    }
+
+    pub fn nr(self) -> u8 {
+        (self.0 >> NR_SHIFT) as u8
+    }
+
+    pub fn size(self) -> usize {
+        ((self.0 >> SIZE_SHIFT) & SIZE_MASK) as usize
+    }
+
+    const fn encode(dir: c_uint, kind: u8, nr: u8, size: usize) -> Self {
+        Self((dir << DIR_SHIFT)
+            | ((size as c_uint & SIZE_MASK) << SIZE_SHIFT)
+            | ((kind as c_uint) << TYPE_SHIFT)
+            | ((nr as c_uint) << NR_SHIFT))
+    }
+}
+
+
+// a type valid for any bytes and without padding, so it can be copied in from and out to
+// the caller, implement it for `#[repr(C)]` structs made of such fields and no padding
+#[allow(clippy::missing_safety_doc)]
+pub unsafe trait Pod: Copy { }
+
+macro_rules! pod {
+    ( $( $T:ty ),* ) => {
+        $( unsafe impl Pod for $T { } )*
+    };
+}
+
+pod! { u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64 }
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] { }
+
+
+// the command argument copied in from the caller, `None` if `input` is too short
+pub fn read_input<T: Pod>(input: &[u8]) -> Option<T> {
+    if input.len() < std::mem::size_of::<T>() {
+        return None;
+    }
+
+    Some(unsafe { input.as_ptr().cast::<T>().read_unaligned() })
+}
+
+// copies `value` out to the caller, `None` if `output` is too short
+pub fn write_output<T: Pod>(output: &mut [u8], value: &T) -> Option<()> {
+    if output.len() < std::mem::size_of::<T>() {
+        return None;
+    }
+
+    unsafe { output.as_mut_ptr().cast::<T>().write_unaligned(*value); }
+
+    Some(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use libc::c_long;
+
+    // `FS_IOC_GETFLAGS`, `_IOR('f', 1, long)`
+    const GET_FLAGS: IoctlCmd = IoctlCmd::read::<c_long>(b'f', 1);
+
+    #[test]
+    fn encode_and_decode() {
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(GET_FLAGS, IoctlCmd(0x8008_6601));
+        assert_eq!(GET_FLAGS.dir(), IoctlDir::READ);
+        assert_eq!(GET_FLAGS.size(), std::mem::size_of::<c_long>());
+        assert_eq!(GET_FLAGS.kind(), b'f');
+        assert_eq!(GET_FLAGS.nr(), 1);
+
+        let cmd = IoctlCmd::read_write::<[u8; 3]>(b'E', 7);
+
+        assert_eq!(cmd.dir(), IoctlDir::READ | IoctlDir::WRITE);
+        assert_eq!((cmd.kind(), cmd.nr(), cmd.size()), (b'E', 7, 3));
+        assert_eq!(IoctlCmd::none(b'E', 7).dir(), IoctlDir::empty());
+    }
+
+    #[test]
+    fn input_and_output() {
+        let mut output = [0u8; 8];
+
+        assert_eq!(write_output(&mut output, &0x1234_5678u32), Some(()));
+        assert_eq!(read_input::<u32>(&output), Some(0x1234_5678));
+        assert_eq!(read_input::<u32>(&output[4..]), Some(0));
+        assert_eq!(read_input::<[u32; 2]>(&output), Some([0x1234_5678, 0]));
+
+        assert_eq!(read_input::<u64>(&output[1..]), None);
+        assert_eq!(write_output(&mut output[5..], &0u32), None);
+    }
+}
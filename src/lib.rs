@@ -1,11 +1,72 @@
+#[macro_use]
+mod macros;
+
 pub mod fuse;
 
+pub mod fallocate;
+pub mod ioctl;
+pub mod permission;
+pub mod rename;
+
+#[cfg(not(feature = "fuse2"))]
+mod async_operations;
+mod attr;
+mod buf;
+mod context;
+#[cfg(not(feature = "fuse2"))]
+mod cuse;
+mod dir;
+mod interrupt;
+mod lock;
+mod lock_manager;
+#[cfg(not(feature = "fuse2"))]
+mod lowlevel;
 mod neg;
+#[cfg(not(feature = "fuse2"))]
+mod notify;
 mod operations;
+mod poll;
+#[cfg(not(feature = "fuse2"))]
+mod reply;
+mod seek;
+#[cfg(not(feature = "fuse2"))]
+mod session;
+mod xattr;
 
 
+#[cfg(not(feature = "fuse2"))]
+pub use async_operations::{ AsyncAdapter, AsyncOperations, BoxFuture, Spawn };
+#[cfg(all(feature = "tokio", not(feature = "fuse2")))]
+pub use async_operations::TokioSpawn;
+pub use attr::{ FileAttr, FileType };
+pub use buf::{ Buf, BufRef, WriteBuf };
+pub use context::Context;
+#[cfg(not(feature = "fuse2"))]
+pub use cuse::{ CharDevice, DeviceInfo, cuse_main };
+pub use dir::{ DirEntry, ReadDir };
+pub use fallocate::FallocateFlags;
+pub use interrupt::{ Interrupt, interrupted, check_interrupted };
+pub use ioctl::{ IoctlCmd, IoctlDir, IoctlFlags, Pod };
+pub use lock::{ FlockOp, LockCmd, LockRange, LockType, PosixLock };
+pub use lock_manager::LockManager;
+#[cfg(not(feature = "fuse2"))]
+pub use lowlevel::{ DirEntryPlus, Entry, LowLevelOperations, SetAttr, TimeOrNow };
 pub use neg::Neg;
+#[cfg(not(feature = "fuse2"))]
+pub use notify::{ Notifier, invalidate_path };
 pub use operations::Operations;
+pub use poll::PollHandle;
+pub use rename::RenameFlags;
+#[cfg(not(feature = "fuse2"))]
+pub use reply::{ ReplyAttr, ReplyBmap, ReplyCreate, ReplyData, ReplyDirectory, ReplyDirectoryPlus };
+#[cfg(not(feature = "fuse2"))]
+pub use reply::{ ReplyEmpty, ReplyEntry, ReplyIoctl, ReplyLock, ReplyLseek, ReplyOpen };
+#[cfg(not(feature = "fuse2"))]
+pub use reply::{ ReplyPoll, ReplyReadlink, ReplyStatfs, ReplyWrite, ReplyXattr };
+pub use seek::{ Extents, Whence };
+#[cfg(not(feature = "fuse2"))]
+pub use session::{ Request, Session };
+pub use xattr::XattrFlags;
 
 use std::ffi::CString;
 use std::convert::TryInto;
@@ -16,6 +77,27 @@ use unwrap::unwrap;
 pub fn fuse_main<T, U>(args: T, ops: U) -> Result<(), i32>
     where T: Iterator<Item=String>,
           U: 'static + Operations
+{
+    fuse_main_real(args, ops, std::ptr::null_mut())
+}
+
+pub fn fuse_main_with_data<T, U, D>(args: T, ops: U, data: D) -> Result<(), i32>
+    where T: Iterator<Item=String>,
+          U: 'static + Operations,
+          D: 'static + Send + Sync
+{
+    let data: *mut context::PrivateData = Box::into_raw(Box::new(std::sync::Arc::new(data)));
+
+    let res = fuse_main_real(args, ops, data.cast());
+
+    unsafe { drop(Box::from_raw(data)); }
+
+    res
+}
+
+fn fuse_main_real<T, U>(args: T, ops: U, user_data: *mut libc::c_void) -> Result<(), i32>
+    where T: Iterator<Item=String>,
+          U: 'static + Operations
 {
     let c_args = args
         .map(|arg| unwrap!(CString::new(arg)));
@@ -32,7 +114,10 @@ pub fn fuse_main<T, U>(args: T, ops: U) -> Result<(), i32>
             c_args.as_mut_ptr(),
             &ops,
             std::mem::size_of::<fuse::fuse_operations>(),
-            std::ptr::null_mut());
+            user_data);
+
+        #[cfg(not(feature = "fuse2"))]
+        notify::FUSE.store(std::ptr::null_mut(), std::sync::atomic::Ordering::Release);
 
         let _: Vec<_> = c_args
             .iter()
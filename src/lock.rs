@@ -0,0 +1,139 @@
+use std::convert::{ TryFrom, TryInto };
+
+use libc::{ c_int, c_short };
+use libc::{ F_GETLK, F_SETLK, F_SETLKW, F_RDLCK, F_WRLCK, F_UNLCK, SEEK_SET };
+use libc::{ LOCK_SH, LOCK_EX, LOCK_UN, LOCK_NB };
+
+use unwrap::unwrap;
+
+use crate::fuse;
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum LockCmd {
+    GetLk,
+    SetLk,
+    SetLkw,
+}
+
+impl LockCmd {
+    pub fn from_raw(cmd: c_int) -> Option<Self> {
+        match cmd {
+            F_GETLK => Some(LockCmd::GetLk),
+            F_SETLK => Some(LockCmd::SetLk),
+            F_SETLKW => Some(LockCmd::SetLkw),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum LockType {
+    Read,
+    Write,
+    Unlock,
+}
+
+impl LockType {
+    pub fn from_raw(kind: c_int) -> Option<Self> {
+        match kind {
+            F_RDLCK => Some(LockType::Read),
+            F_WRLCK => Some(LockType::Write),
+            F_UNLCK => Some(LockType::Unlock),
+            _ => None,
+        }
+    }
+
+    pub fn raw(self) -> c_int {
+        match self {
+            LockType::Read => F_RDLCK,
+            LockType::Write => F_WRLCK,
+            LockType::Unlock => F_UNLCK,
+        }
+    }
+}
+
+
+// `end` is inclusive, `u64::MAX` stands for the end of file
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct LockRange {
+    pub start: u64,
+    pub end: u64,
+    pub pid: fuse::pid_t,
+}
+
+impl LockRange {
+    pub fn overlaps(&self, other: &LockRange) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct PosixLock {
+    pub kind: LockType,
+    pub range: LockRange,
+    // `fuse_file_info::lock_owner` of the request, identifies the lock holder
+    pub owner: u64,
+}
+
+impl PosixLock {
+    pub(crate) fn from_flock(lock: &fuse::flock, owner: u64) -> Option<Self> {
+        let (start, len) = (lock.l_start, lock.l_len);
+
+        let (start, end) = if len > 0 {
+            (start, start + len - 1)
+        } else if len < 0 {
+            (start + len, start - 1)
+        } else {
+            (start, -1)
+        };
+
+        Some(Self {
+            kind: LockType::from_raw(lock.l_type.into())?,
+            range: LockRange {
+                start: start.try_into().ok()?,
+                end: end.try_into().unwrap_or(u64::MAX),
+                pid: lock.l_pid,
+            },
+            owner,
+        })
+    }
+
+    pub(crate) fn fill(&self, lock: &mut fuse::flock) {
+        let len = if self.range.end == u64::MAX {
+            0
+        } else {
+            self.range.end - self.range.start + 1
+        };
+
+        lock.l_type = unwrap!(c_short::try_from(self.kind.raw()));
+        lock.l_whence = unwrap!(c_short::try_from(SEEK_SET));
+        lock.l_start = unwrap!(self.range.start.try_into());
+        lock.l_len = unwrap!(len.try_into());
+        lock.l_pid = self.range.pid;
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum FlockOp {
+    Shared,
+    Exclusive,
+    Unlock,
+}
+
+impl FlockOp {
+    // the operation and whether LOCK_NB is set
+    pub fn from_raw(op: c_int) -> Option<(Self, bool)> {
+        let kind = match op & !LOCK_NB {
+            LOCK_SH => FlockOp::Shared,
+            LOCK_EX => FlockOp::Exclusive,
+            LOCK_UN => FlockOp::Unlock,
+            _ => return None,
+        };
+
+        Some((kind, op & LOCK_NB != 0))
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{ Condvar, Mutex, MutexGuard };
+use std::time::Duration;
+
+use libc::{ EAGAIN, EINTR };
+
+use unwrap::unwrap;
+
+use crate::{ fuse, interrupted, Neg, neg };
+use crate::{ FlockOp, LockCmd, LockRange, LockType, PosixLock };
+
+
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+
+// local POSIX record locks and BSD flocks, keyed by inode or file handle
+pub struct LockManager<K = u64> {
+    state: Mutex<State<K>>,
+    cond: Condvar,
+}
+
+struct State<K> {
+    posix: HashMap<K, Vec<PosixLock>>,
+    // owner and whether the lock is exclusive
+    flocks: HashMap<K, Vec<(u64, bool)>>,
+}
+
+impl<K: Eq + Hash + Clone> Default for LockManager<K> {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State { posix: HashMap::new(), flocks: HashMap::new() }),
+            cond: Condvar::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone> LockManager<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // implements `Operations::lock`
+    pub fn lock(&self, key: K, cmd: LockCmd, lock: &mut PosixLock) -> Result<(), Neg> {
+        let mut state = unwrap!(self.state.lock());
+
+        if cmd == LockCmd::GetLk {
+            let locks = state.posix.get(&key).map(|x| x.as_slice()).unwrap_or(&[]);
+
+            match locks.iter().find(|x| conflicts(x, lock)) {
+                Some(x) => *lock = *x,
+                None => lock.kind = LockType::Unlock,
+            }
+
+            return Ok(());
+        }
+
+        if lock.kind != LockType::Unlock {
+            while state.posix.get(&key).is_some_and(|x| x.iter().any(|x| conflicts(x, lock))) {
+                if cmd == LockCmd::SetLk {
+                    return Err(neg!(-EAGAIN));
+                }
+
+                state = self.wait(state)?;
+            }
+        }
+
+        let locks = state.posix.entry(key.clone()).or_insert_with(Vec::new);
+
+        set_range(locks, lock);
+
+        if locks.is_empty() {
+            state.posix.remove(&key);
+        }
+
+        self.cond.notify_all();
+
+        Ok(())
+    }
+
+    // implements `Operations::flock`, `owner` is `fuse_file_info::lock_owner`
+    pub fn flock(&self, key: K, owner: u64, op: FlockOp, nonblock: bool) -> Result<(), Neg> {
+        let mut state = unwrap!(self.state.lock());
+
+        let exclusive = match op {
+            FlockOp::Unlock => {
+                remove_flock(&mut state, &key, owner);
+                self.cond.notify_all();
+
+                return Ok(());
+            },
+            FlockOp::Shared => false,
+            FlockOp::Exclusive => true,
+        };
+
+        loop {
+            let busy = state.flocks.get(&key).is_some_and(|x| {
+                x.iter().any(|&(o, excl)| o != owner && (excl || exclusive))
+            });
+
+            if !busy {
+                break;
+            }
+
+            if nonblock {
+                return Err(neg!(-EAGAIN));
+            }
+
+            state = self.wait(state)?;
+        }
+
+        let flocks = state.flocks.entry(key).or_insert_with(Vec::new);
+
+        match flocks.iter_mut().find(|(o, _)| *o == owner) {
+            Some(x) => x.1 = exclusive,
+            None => flocks.push((owner, exclusive)),
+        }
+
+        self.cond.notify_all();
+
+        Ok(())
+    }
+
+    // drops every record lock of `owner`, as done when a file is closed
+    pub fn unlock_owner(&self, key: &K, owner: u64) {
+        let mut state = unwrap!(self.state.lock());
+
+        if let Some(locks) = state.posix.get_mut(key) {
+            locks.retain(|x| x.owner != owner);
+
+            if locks.is_empty() {
+                state.posix.remove(key);
+            }
+        }
+
+        self.cond.notify_all();
+    }
+
+    // implements `Operations::release`, dropping the flock held through `fi` if asked to
+    pub fn release(&self, key: &K, fi: &fuse::fuse_file_info) {
+        if fi.flock_release() != 0 {
+            let mut state = unwrap!(self.state.lock());
+
+            remove_flock(&mut state, key, fi.lock_owner);
+            self.cond.notify_all();
+        }
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, State<K>>) -> Result<MutexGuard<'a, State<K>>, Neg> {
+        let (state, _) = unwrap!(self.cond.wait_timeout(state, WAIT_INTERVAL));
+
+        if interrupted() {
+            Err(neg!(-EINTR))
+        } else {
+            Ok(state)
+        }
+    }
+}
+
+
+fn conflicts(held: &PosixLock, lock: &PosixLock) -> bool {
+    held.owner != lock.owner
+        && held.range.overlaps(&lock.range)
+        && (held.kind == LockType::Write || lock.kind == LockType::Write)
+}
+
+fn adjacent(a: &LockRange, b: &LockRange) -> bool {
+    a.overlaps(b)
+        || (a.end != u64::MAX && a.end + 1 == b.start)
+        || (b.end != u64::MAX && b.end + 1 == a.start)
+}
+
+// replaces the range of `lock.owner` covered by `lock`, splitting and merging its locks
+fn set_range(locks: &mut Vec<PosixLock>, lock: &PosixLock) {
+    let range = lock.range;
+    let mut kept = Vec::with_capacity(locks.len() + 2);
+
+    for held in locks.drain(..) {
+        if held.owner != lock.owner || !held.range.overlaps(&range) {
+            kept.push(held);
+            continue;
+        }
+
+        if held.range.start < range.start {
+            kept.push(PosixLock {
+                range: LockRange { end: range.start - 1, ..held.range },
+                ..held
+            });
+        }
+
+        if range.end != u64::MAX && held.range.end > range.end {
+            kept.push(PosixLock {
+                range: LockRange { start: range.end + 1, ..held.range },
+                ..held
+            });
+        }
+    }
+
+    if lock.kind != LockType::Unlock {
+        let mut new = *lock;
+
+        kept.retain(|held| {
+            let merge = held.owner == new.owner
+                && held.kind == new.kind
+                && adjacent(&held.range, &new.range);
+
+            if merge {
+                new.range.start = new.range.start.min(held.range.start);
+                new.range.end = new.range.end.max(held.range.end);
+            }
+
+            !merge
+        });
+
+        kept.push(new);
+    }
+
+    *locks = kept;
+}
+
+fn remove_flock<K: Eq + Hash>(state: &mut State<K>, key: &K, owner: u64) {
+    if let Some(flocks) = state.flocks.get_mut(key) {
+        flocks.retain(|&(o, _)| o != owner);
+
+        if flocks.is_empty() {
+            state.flocks.remove(key);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock(kind: LockType, start: u64, end: u64, owner: u64) -> PosixLock {
+        PosixLock { kind, range: LockRange { start, end, pid: owner as _ }, owner }
+    }
+
+    #[test]
+    fn split_and_merge() {
+        let locks = LockManager::new();
+
+        locks.lock(1, LockCmd::SetLk, &mut lock(LockType::Write, 0, 99, 1)).unwrap();
+        locks.lock(1, LockCmd::SetLk, &mut lock(LockType::Unlock, 40, 59, 1)).unwrap();
+
+        let mut probe = lock(LockType::Write, 50, 50, 2);
+        locks.lock(1, LockCmd::GetLk, &mut probe).unwrap();
+        assert_eq!(probe.kind, LockType::Unlock);
+
+        let mut probe = lock(LockType::Read, 60, 60, 2);
+        locks.lock(1, LockCmd::GetLk, &mut probe).unwrap();
+        assert_eq!(probe, lock(LockType::Write, 60, 99, 1));
+
+        locks.lock(1, LockCmd::SetLk, &mut lock(LockType::Write, 40, 59, 1)).unwrap();
+
+        let mut probe = lock(LockType::Read, 0, u64::MAX, 2);
+        locks.lock(1, LockCmd::GetLk, &mut probe).unwrap();
+        assert_eq!(probe, lock(LockType::Write, 0, 99, 1));
+    }
+
+    #[test]
+    fn conflicts() {
+        let locks = LockManager::new();
+
+        locks.lock(1, LockCmd::SetLk, &mut lock(LockType::Read, 0, 9, 1)).unwrap();
+        locks.lock(1, LockCmd::SetLk, &mut lock(LockType::Read, 5, 14, 2)).unwrap();
+
+        let res = locks.lock(1, LockCmd::SetLk, &mut lock(LockType::Write, 8, 8, 2));
+        assert_eq!(res, Err(neg!(-EAGAIN)));
+
+        locks.unlock_owner(&1, 1);
+        locks.lock(1, LockCmd::SetLk, &mut lock(LockType::Write, 8, 8, 2)).unwrap();
+    }
+
+    #[test]
+    fn flock() {
+        let locks = LockManager::new();
+
+        locks.flock(1, 1, FlockOp::Shared, true).unwrap();
+        locks.flock(1, 2, FlockOp::Shared, true).unwrap();
+        assert_eq!(locks.flock(1, 2, FlockOp::Exclusive, true), Err(neg!(-EAGAIN)));
+
+        locks.flock(1, 1, FlockOp::Unlock, true).unwrap();
+        locks.flock(1, 2, FlockOp::Exclusive, true).unwrap();
+    }
+}
@@ -0,0 +1,806 @@
+use std::convert::TryInto;
+use std::sync::{ Mutex, MutexGuard };
+use std::time::{ Duration, SystemTime };
+
+use libc::{ c_int, c_char, c_uint, c_void };
+use libc::{ EINVAL, ENOSYS, EOPNOTSUPP };
+
+use unwrap::unwrap;
+
+use crate::{ attr, fuse, interrupt, Neg, neg };
+use crate::{ FallocateFlags, FileAttr, FlockOp, LockCmd, PosixLock, RenameFlags, Request };
+use crate::{ ReplyAttr, ReplyBmap, ReplyCreate, ReplyData, ReplyDirectory, ReplyDirectoryPlus };
+use crate::{ ReplyEmpty, ReplyEntry, ReplyLock, ReplyLseek, ReplyOpen, ReplyReadlink };
+use crate::{ ReplyStatfs, ReplyWrite, ReplyXattr, Whence, WriteBuf, XattrFlags };
+
+
+#[derive(Copy, Clone, Debug)]
+pub struct Entry {
+    pub attr: FileAttr,
+    pub generation: u64,
+    pub attr_ttl: Duration,
+    pub entry_ttl: Duration,
+}
+
+impl Entry {
+    pub fn new(attr: FileAttr, ttl: Duration) -> Self {
+        Self { attr, generation: 0, attr_ttl: ttl, entry_ttl: ttl }
+    }
+
+    pub(crate) fn raw(&self) -> fuse::fuse_entry_param {
+        let mut e = unsafe {
+            std::mem::MaybeUninit::<fuse::fuse_entry_param>::zeroed().assume_init()
+        };
+
+        e.ino = self.attr.ino;
+        e.generation = self.generation;
+        self.attr.fill(&mut e.attr);
+        e.attr_timeout = self.attr_ttl.as_secs_f64();
+        e.entry_timeout = self.entry_ttl.as_secs_f64();
+
+        e
+    }
+}
+
+
+// an entry of `readdirplus`, returning it counts as a lookup except for `.` and `..`
+#[derive(Clone, Debug)]
+pub struct DirEntryPlus {
+    pub name: String,
+    pub entry: Entry,
+}
+
+impl DirEntryPlus {
+    pub fn new<T: Into<String>>(name: T, entry: Entry) -> Self {
+        Self { name: name.into(), entry }
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TimeOrNow {
+    Time(SystemTime),
+    Now,
+}
+
+// the attributes to change in `setattr`, `None` for the ones left as is
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SetAttr {
+    pub mode: Option<fuse::mode_t>,
+    pub uid: Option<fuse::uid_t>,
+    pub gid: Option<fuse::gid_t>,
+    pub size: Option<u64>,
+    pub atime: Option<TimeOrNow>,
+    pub mtime: Option<TimeOrNow>,
+    pub ctime: Option<SystemTime>,
+}
+
+impl SetAttr {
+    fn from_raw(stbuf: &fuse::stat, to_set: c_int) -> Self {
+        let set = |flag: u32| to_set as u32 & flag != 0;
+
+        let time = |flag, now, ts| {
+            if set(now) {
+                Some(TimeOrNow::Now)
+            } else if set(flag) {
+                Some(TimeOrNow::Time(attr::system_time(ts)))
+            } else {
+                None
+            }
+        };
+
+        let ctime = Some(attr::system_time(&stbuf.st_ctim));
+
+        Self {
+            mode: Some(stbuf.st_mode).filter(|_| set(fuse::FUSE_SET_ATTR_MODE)),
+            uid: Some(stbuf.st_uid).filter(|_| set(fuse::FUSE_SET_ATTR_UID)),
+            gid: Some(stbuf.st_gid).filter(|_| set(fuse::FUSE_SET_ATTR_GID)),
+            size: Some(stbuf.st_size as u64).filter(|_| set(fuse::FUSE_SET_ATTR_SIZE)),
+            atime: time(fuse::FUSE_SET_ATTR_ATIME, fuse::FUSE_SET_ATTR_ATIME_NOW, &stbuf.st_atim),
+            mtime: time(fuse::FUSE_SET_ATTR_MTIME, fuse::FUSE_SET_ATTR_MTIME_NOW, &stbuf.st_mtim),
+            ctime: ctime.filter(|_| set(fuse::FUSE_SET_ATTR_CTIME)),
+        }
+    }
+}
+
+
+// the inode based API of `fuse_lowlevel.h`, every inode returned in an `Entry`
+// is referenced until the kernel forgets it through `forget` or `batch_forget`,
+// each request is answered through its reply, which may be sent from another thread,
+// `readdirplus`, `lock` and `flock` are only called after `init` adds
+// `FUSE_CAP_READDIRPLUS`, `FUSE_CAP_POSIX_LOCKS` or `FUSE_CAP_FLOCK_LOCKS` to `conn.want`
+#[allow(unused_variables)]
+#[allow(clippy::too_many_arguments)]
+pub trait LowLevelOperations {
+    fn init(&mut self, conn: &mut fuse::fuse_conn_info) { }
+    fn destroy(&mut self) { }
+
+    fn lookup(&mut self, req: &Request, parent: u64, name: &str, reply: ReplyEntry) {
+        reply.error(neg!(-ENOSYS));
+    }
+
+    fn forget(&mut self, req: &Request, ino: u64, nlookup: u64) { }
+
+    fn batch_forget(&mut self, req: &Request, nodes: &[fuse::fuse_forget_data]) {
+        for node in nodes {
+            self.forget(req, node.ino, node.nlookup);
+        }
+    }
+
+    fn getattr(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: Option<&mut fuse::fuse_file_info>,
+        reply: ReplyAttr) { reply.error(neg!(-ENOSYS)); }
+
+    fn setattr(&mut self,
+        req: &Request,
+        ino: u64,
+        attr: SetAttr,
+        fi: Option<&mut fuse::fuse_file_info>,
+        reply: ReplyAttr) { reply.error(neg!(-ENOSYS)); }
+
+    fn readlink(&mut self, req: &Request, ino: u64, reply: ReplyReadlink) {
+        reply.error(neg!(-ENOSYS));
+    }
+
+    fn mknod(&mut self,
+        req: &Request,
+        parent: u64,
+        name: &str,
+        mode: fuse::mode_t,
+        rdev: fuse::dev_t,
+        reply: ReplyEntry) { reply.error(neg!(-ENOSYS)); }
+
+    fn mkdir(&mut self,
+        req: &Request,
+        parent: u64,
+        name: &str,
+        mode: fuse::mode_t,
+        reply: ReplyEntry) { reply.error(neg!(-ENOSYS)); }
+
+    fn unlink(&mut self, req: &Request, parent: u64, name: &str, reply: ReplyEmpty) {
+        reply.error(neg!(-ENOSYS));
+    }
+
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &str, reply: ReplyEmpty) {
+        reply.error(neg!(-ENOSYS));
+    }
+
+    fn symlink(&mut self,
+        req: &Request,
+        link: &str,
+        parent: u64,
+        name: &str,
+        reply: ReplyEntry) { reply.error(neg!(-ENOSYS)); }
+
+    fn rename(&mut self,
+        req: &Request,
+        parent: u64,
+        name: &str,
+        newparent: u64,
+        newname: &str,
+        flags: RenameFlags,
+        reply: ReplyEmpty) { reply.error(neg!(-ENOSYS)); }
+
+    fn link(&mut self,
+        req: &Request,
+        ino: u64,
+        newparent: u64,
+        newname: &str,
+        reply: ReplyEntry) { reply.error(neg!(-ENOSYS)); }
+
+    fn open(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyOpen) { reply.opened(fi); }
+
+    fn read(&mut self,
+        req: &Request,
+        ino: u64,
+        size: usize,
+        offset: fuse::off_t,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyData) { reply.error(neg!(-ENOSYS)); }
+
+    fn write(&mut self,
+        req: &Request,
+        ino: u64,
+        data: &[u8],
+        offset: fuse::off_t,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyWrite) { reply.error(neg!(-ENOSYS)); }
+
+    fn flush(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyEmpty) { reply.error(neg!(-ENOSYS)); }
+
+    fn release(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyEmpty) { reply.ok(); }
+
+    fn fsync(&mut self,
+        req: &Request,
+        ino: u64,
+        datasync: bool,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyEmpty) { reply.error(neg!(-ENOSYS)); }
+
+    fn opendir(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyOpen) { reply.opened(fi); }
+
+    fn readdir(&mut self,
+        req: &Request,
+        ino: u64,
+        offset: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyDirectory) { reply.error(neg!(-ENOSYS)); }
+
+    fn readdirplus(&mut self,
+        req: &Request,
+        ino: u64,
+        offset: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyDirectoryPlus) { reply.error(neg!(-ENOSYS)); }
+
+    fn releasedir(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyEmpty) { reply.ok(); }
+
+    fn fsyncdir(&mut self,
+        req: &Request,
+        ino: u64,
+        datasync: bool,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyEmpty) { reply.error(neg!(-ENOSYS)); }
+
+    fn statfs(&mut self, req: &Request, ino: u64, reply: ReplyStatfs) {
+        reply.error(neg!(-ENOSYS));
+    }
+
+    fn setxattr(&mut self,
+        req: &Request,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: XattrFlags,
+        reply: ReplyEmpty) { reply.error(neg!(-ENOSYS)); }
+
+    fn getxattr(&mut self, req: &Request, ino: u64, name: &str, reply: ReplyXattr) {
+        reply.error(neg!(-ENOSYS));
+    }
+
+    fn listxattr(&mut self, req: &Request, ino: u64, reply: ReplyXattr) {
+        reply.error(neg!(-ENOSYS));
+    }
+
+    fn removexattr(&mut self, req: &Request, ino: u64, name: &str, reply: ReplyEmpty) {
+        reply.error(neg!(-ENOSYS));
+    }
+
+    fn access(&mut self, req: &Request, ino: u64, mask: c_int, reply: ReplyEmpty) {
+        reply.error(neg!(-ENOSYS));
+    }
+
+    fn create(&mut self,
+        req: &Request,
+        parent: u64,
+        name: &str,
+        mode: fuse::mode_t,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyCreate) { reply.error(neg!(-ENOSYS)); }
+
+    fn lock(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: &mut fuse::fuse_file_info,
+        cmd: LockCmd,
+        lock: PosixLock,
+        reply: ReplyLock) { reply.error(neg!(-ENOSYS)); }
+
+    fn bmap(&mut self, req: &Request, ino: u64, blocksize: usize, idx: u64, reply: ReplyBmap) {
+        reply.error(neg!(-ENOSYS));
+    }
+
+    fn flock(&mut self,
+        req: &Request,
+        ino: u64,
+        fi: &mut fuse::fuse_file_info,
+        op: FlockOp,
+        nonblock: bool,
+        reply: ReplyEmpty) { reply.error(neg!(-ENOSYS)); }
+
+    fn fallocate(&mut self,
+        req: &Request,
+        ino: u64,
+        mode: FallocateFlags,
+        offset: fuse::off_t,
+        length: fuse::off_t,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyEmpty) { reply.error(neg!(-ENOSYS)); }
+
+    fn lseek(&mut self,
+        req: &Request,
+        ino: u64,
+        off: fuse::off_t,
+        whence: Whence,
+        fi: &mut fuse::fuse_file_info,
+        reply: ReplyLseek) { reply.error(neg!(-ENOSYS)); }
+
+    // the data asked for by `Notifier::retrieve` with `cookie`
+    fn retrieve_reply(&mut self,
+        req: &Request,
+        cookie: u64,
+        ino: u64,
+        offset: fuse::off_t,
+        data: WriteBuf<'_>) { }
+}
+
+
+pub(crate) type UserData = Mutex<Box<dyn LowLevelOperations + Send>>;
+
+// libfuse wants these whenever their handlers are registered, they are left to `init`
+const OPT_IN_CAPS: c_uint = (fuse::FUSE_CAP_READDIRPLUS
+    | fuse::FUSE_CAP_READDIRPLUS_AUTO
+    | fuse::FUSE_CAP_POSIX_LOCKS
+    | fuse::FUSE_CAP_FLOCK_LOCKS) as c_uint;
+
+unsafe fn user_ops<'a>(userdata: *mut c_void)
+    -> MutexGuard<'a, Box<dyn LowLevelOperations + Send>>
+{
+    unwrap!(ptr_mut!(userdata.cast::<UserData>()).lock())
+}
+
+macro_rules! ll_op {
+    ( $req:ident, $method:ident, $( $arg:expr ),* ) => {
+        user_ops(fuse::fuse_req_userdata($req)).$method(&Request::new($req), $( $arg, )*)
+    };
+}
+
+unsafe fn reply_err(req: fuse::fuse_req_t, e: c_int) {
+    fuse::fuse_reply_err(req, e);
+}
+
+// answers the requests without a reply, the `Request` handed out registered them
+unsafe fn reply_none(req: fuse::fuse_req_t) {
+    interrupt::forget_request(req);
+    fuse::fuse_reply_none(req);
+}
+
+unsafe extern "C" fn init(userdata: *mut c_void, conn: *mut fuse::fuse_conn_info) {
+    let conn = ptr_mut!(conn);
+
+    conn.want &= !OPT_IN_CAPS;
+    user_ops(userdata).init(conn);
+}
+
+unsafe extern "C" fn destroy(userdata: *mut c_void) {
+    user_ops(userdata).destroy();
+}
+
+unsafe extern "C" fn lookup(req: fuse::fuse_req_t, parent: fuse::fuse_ino_t, name: *const c_char) {
+    ll_op!(req, lookup, parent, ptr_str!(name), ReplyEntry::new(req));
+}
+
+unsafe extern "C" fn forget(req: fuse::fuse_req_t, ino: fuse::fuse_ino_t, nlookup: u64) {
+    ll_op!(req, forget, ino, nlookup);
+
+    reply_none(req);
+}
+
+unsafe extern "C" fn forget_multi(
+    req: fuse::fuse_req_t,
+    count: usize,
+    forgets: *mut fuse::fuse_forget_data)
+{
+    ll_op!(req, batch_forget, std::slice::from_raw_parts(forgets, count));
+
+    reply_none(req);
+}
+
+unsafe extern "C" fn getattr(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    ll_op!(req, getattr, ino, fi.as_mut(), ReplyAttr::new(req));
+}
+
+unsafe extern "C" fn setattr(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    attr: *mut fuse::stat,
+    to_set: c_int,
+    fi: *mut fuse::fuse_file_info)
+{
+    let attr = SetAttr::from_raw(ptr_mut!(attr), to_set);
+
+    ll_op!(req, setattr, ino, attr, fi.as_mut(), ReplyAttr::new(req));
+}
+
+unsafe extern "C" fn readlink(req: fuse::fuse_req_t, ino: fuse::fuse_ino_t) {
+    ll_op!(req, readlink, ino, ReplyReadlink::new(req));
+}
+
+unsafe extern "C" fn mknod(
+    req: fuse::fuse_req_t,
+    parent: fuse::fuse_ino_t,
+    name: *const c_char,
+    mode: fuse::mode_t,
+    rdev: fuse::dev_t)
+{
+    ll_op!(req, mknod, parent, ptr_str!(name), mode, rdev, ReplyEntry::new(req));
+}
+
+unsafe extern "C" fn mkdir(
+    req: fuse::fuse_req_t,
+    parent: fuse::fuse_ino_t,
+    name: *const c_char,
+    mode: fuse::mode_t)
+{
+    ll_op!(req, mkdir, parent, ptr_str!(name), mode, ReplyEntry::new(req));
+}
+
+unsafe extern "C" fn unlink(req: fuse::fuse_req_t, parent: fuse::fuse_ino_t, name: *const c_char) {
+    ll_op!(req, unlink, parent, ptr_str!(name), ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn rmdir(req: fuse::fuse_req_t, parent: fuse::fuse_ino_t, name: *const c_char) {
+    ll_op!(req, rmdir, parent, ptr_str!(name), ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn symlink(
+    req: fuse::fuse_req_t,
+    link: *const c_char,
+    parent: fuse::fuse_ino_t,
+    name: *const c_char)
+{
+    ll_op!(req, symlink, ptr_str!(link), parent, ptr_str!(name), ReplyEntry::new(req));
+}
+
+unsafe extern "C" fn rename(
+    req: fuse::fuse_req_t,
+    parent: fuse::fuse_ino_t,
+    name: *const c_char,
+    newparent: fuse::fuse_ino_t,
+    newname: *const c_char,
+    flags: libc::c_uint)
+{
+    let flags = match RenameFlags::from_bits(flags) {
+        Some(x) => x,
+        None => return reply_err(req, EINVAL),
+    };
+
+    ll_op!(req, rename,
+        parent, ptr_str!(name),
+        newparent, ptr_str!(newname),
+        flags, ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn link(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    newparent: fuse::fuse_ino_t,
+    newname: *const c_char)
+{
+    ll_op!(req, link, ino, newparent, ptr_str!(newname), ReplyEntry::new(req));
+}
+
+unsafe extern "C" fn open(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    ll_op!(req, open, ino, ptr_mut!(fi), ReplyOpen::new(req));
+}
+
+unsafe extern "C" fn read(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    size: usize,
+    off: fuse::off_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    ll_op!(req, read, ino, size, off, ptr_mut!(fi), ReplyData::new(req));
+}
+
+unsafe extern "C" fn write(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    buf: *const c_char,
+    size: usize,
+    off: fuse::off_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    let data = std::slice::from_raw_parts(buf.cast(), size);
+
+    ll_op!(req, write, ino, data, off, ptr_mut!(fi), ReplyWrite::new(req));
+}
+
+unsafe extern "C" fn flush(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    ll_op!(req, flush, ino, ptr_mut!(fi), ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn release(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    ll_op!(req, release, ino, ptr_mut!(fi), ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn fsync(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    datasync: c_int,
+    fi: *mut fuse::fuse_file_info)
+{
+    ll_op!(req, fsync, ino, datasync != 0, ptr_mut!(fi), ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn opendir(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    ll_op!(req, opendir, ino, ptr_mut!(fi), ReplyOpen::new(req));
+}
+
+unsafe extern "C" fn readdir(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    size: usize,
+    off: fuse::off_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    let offset = unwrap!(off.try_into());
+
+    ll_op!(req, readdir, ino, offset, ptr_mut!(fi), ReplyDirectory::new(req, size, offset));
+}
+
+unsafe extern "C" fn readdirplus(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    size: usize,
+    off: fuse::off_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    let offset = unwrap!(off.try_into());
+
+    ll_op!(req, readdirplus, ino, offset, ptr_mut!(fi), ReplyDirectoryPlus::new(req, size, offset));
+}
+
+unsafe extern "C" fn releasedir(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    ll_op!(req, releasedir, ino, ptr_mut!(fi), ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn fsyncdir(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    datasync: c_int,
+    fi: *mut fuse::fuse_file_info)
+{
+    ll_op!(req, fsyncdir, ino, datasync != 0, ptr_mut!(fi), ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn statfs(req: fuse::fuse_req_t, ino: fuse::fuse_ino_t) {
+    ll_op!(req, statfs, ino, ReplyStatfs::new(req));
+}
+
+unsafe extern "C" fn setxattr(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    name: *const c_char,
+    value: *const c_char,
+    size: usize,
+    flags: c_int)
+{
+    let flags = match XattrFlags::from_bits(flags) {
+        Some(x) => x,
+        None => return reply_err(req, EINVAL),
+    };
+
+    let value = std::slice::from_raw_parts(value.cast(), size);
+
+    ll_op!(req, setxattr, ino, ptr_str!(name), value, flags, ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn getxattr(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    name: *const c_char,
+    size: usize)
+{
+    ll_op!(req, getxattr, ino, ptr_str!(name), ReplyXattr::new(req, size));
+}
+
+unsafe extern "C" fn listxattr(req: fuse::fuse_req_t, ino: fuse::fuse_ino_t, size: usize) {
+    ll_op!(req, listxattr, ino, ReplyXattr::new(req, size));
+}
+
+unsafe extern "C" fn removexattr(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    name: *const c_char)
+{
+    ll_op!(req, removexattr, ino, ptr_str!(name), ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn access(req: fuse::fuse_req_t, ino: fuse::fuse_ino_t, mask: c_int) {
+    ll_op!(req, access, ino, mask, ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn create(
+    req: fuse::fuse_req_t,
+    parent: fuse::fuse_ino_t,
+    name: *const c_char,
+    mode: fuse::mode_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    ll_op!(req, create, parent, ptr_str!(name), mode, ptr_mut!(fi), ReplyCreate::new(req));
+}
+
+unsafe fn lock(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    fi: *mut fuse::fuse_file_info,
+    cmd: LockCmd,
+    lock: *mut fuse::flock)
+{
+    let fi = ptr_mut!(fi);
+
+    match PosixLock::from_flock(ptr_mut!(lock), fi.lock_owner) {
+        Some(x) => ll_op!(req, lock, ino, fi, cmd, x, ReplyLock::new(req)),
+        None => reply_err(req, EINVAL),
+    }
+}
+
+unsafe extern "C" fn getlk(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    fi: *mut fuse::fuse_file_info,
+    flock: *mut fuse::flock)
+{
+    lock(req, ino, fi, LockCmd::GetLk, flock);
+}
+
+unsafe extern "C" fn setlk(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    fi: *mut fuse::fuse_file_info,
+    flock: *mut fuse::flock,
+    sleep: c_int)
+{
+    lock(req, ino, fi, if sleep != 0 { LockCmd::SetLkw } else { LockCmd::SetLk }, flock);
+}
+
+unsafe extern "C" fn bmap(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    blocksize: usize,
+    idx: u64)
+{
+    ll_op!(req, bmap, ino, blocksize, idx, ReplyBmap::new(req));
+}
+
+unsafe extern "C" fn flock(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    fi: *mut fuse::fuse_file_info,
+    op: c_int)
+{
+    let (op, nonblock) = match FlockOp::from_raw(op) {
+        Some(x) => x,
+        None => return reply_err(req, EINVAL),
+    };
+
+    ll_op!(req, flock, ino, ptr_mut!(fi), op, nonblock, ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn fallocate(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    mode: c_int,
+    offset: fuse::off_t,
+    length: fuse::off_t,
+    fi: *mut fuse::fuse_file_info)
+{
+    let mode = match FallocateFlags::from_bits(mode) {
+        Some(x) => x,
+        None => return reply_err(req, EOPNOTSUPP),
+    };
+
+    ll_op!(req, fallocate, ino, mode, offset, length, ptr_mut!(fi), ReplyEmpty::new(req));
+}
+
+unsafe extern "C" fn lseek(
+    req: fuse::fuse_req_t,
+    ino: fuse::fuse_ino_t,
+    off: fuse::off_t,
+    whence: c_int,
+    fi: *mut fuse::fuse_file_info)
+{
+    match Whence::from_raw(whence) {
+        Some(x) => ll_op!(req, lseek, ino, off, x, ptr_mut!(fi), ReplyLseek::new(req)),
+        None => reply_err(req, EINVAL),
+    }
+}
+
+unsafe extern "C" fn retrieve_reply(
+    req: fuse::fuse_req_t,
+    cookie: *mut c_void,
+    ino: fuse::fuse_ino_t,
+    offset: fuse::off_t,
+    bufv: *mut fuse::fuse_bufvec)
+{
+    ll_op!(req, retrieve_reply, cookie as usize as u64, ino, offset, WriteBuf::new(ptr_mut!(bufv)));
+
+    reply_none(req);
+}
+
+pub(crate) fn fuse_lowlevel_ops_new() -> fuse::fuse_lowlevel_ops {
+    fuse::fuse_lowlevel_ops {
+        init: Some(init),
+        destroy: Some(destroy),
+        lookup: Some(lookup),
+        forget: Some(forget),
+        getattr: Some(getattr),
+        setattr: Some(setattr),
+        readlink: Some(readlink),
+        mknod: Some(mknod),
+        mkdir: Some(mkdir),
+        unlink: Some(unlink),
+        rmdir: Some(rmdir),
+        symlink: Some(symlink),
+        rename: Some(rename),
+        link: Some(link),
+        open: Some(open),
+        read: Some(read),
+        write: Some(write),
+        flush: Some(flush),
+        release: Some(release),
+        fsync: Some(fsync),
+        opendir: Some(opendir),
+        readdir: Some(readdir),
+        releasedir: Some(releasedir),
+        fsyncdir: Some(fsyncdir),
+        statfs: Some(statfs),
+        setxattr: Some(setxattr),
+        getxattr: Some(getxattr),
+        listxattr: Some(listxattr),
+        removexattr: Some(removexattr),
+        access: Some(access),
+        create: Some(create),
+        getlk: Some(getlk),
+        setlk: Some(setlk),
+        bmap: Some(bmap),
+        ioctl: None,
+        poll: None,
+        write_buf: None,
+        retrieve_reply: Some(retrieve_reply),
+        forget_multi: Some(forget_multi),
+        flock: Some(flock),
+        fallocate: Some(fallocate),
+        readdirplus: Some(readdirplus),
+        copy_file_range: None,
+        lseek: Some(lseek),
+    }
+}
@@ -0,0 +1,17 @@
+macro_rules! ptr_str {
+    ( $ptr:expr ) => {
+        match std::ffi::CStr::from_ptr($ptr).to_str() {
+            Ok(x) => x,
+            Err(e) => panic!("convert '*const c_char' to '&str' failed: {:?}", e),
+        }
+    };
+}
+
+macro_rules! ptr_mut {
+    ( $ptr:expr ) => {
+        match $ptr.as_mut() {
+            Some(x) => x,
+            None => panic!("try to convert a null ptr to mutable reference"),
+        }
+    };
+}
@@ -0,0 +1,124 @@
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::sync::{ Arc, RwLock };
+use std::sync::atomic::{ AtomicPtr, Ordering };
+
+use libc::{ c_int, c_void };
+use libc::ENOTCONN;
+
+use unwrap::unwrap;
+
+use crate::{ fuse, Neg, neg };
+
+
+// the `struct fuse` of the running high-level filesystem, set up by `init`
+pub(crate) static FUSE: AtomicPtr<fuse::fuse> = AtomicPtr::new(std::ptr::null_mut());
+
+// drops the cached data and attributes of `path` of the filesystem run by `fuse_main`
+pub fn invalidate_path(path: &str) -> Result<(), Neg> {
+    let f = FUSE.load(Ordering::Acquire);
+
+    if f.is_null() {
+        return Err(neg!(-ENOTCONN));
+    }
+
+    let path = unwrap!(CString::new(path));
+
+    result(unsafe { fuse::fuse_invalidate_path(f, path.as_ptr()) })
+}
+
+
+pub(crate) struct SessionPtr(pub(crate) *mut fuse::fuse_session);
+
+// libfuse serializes the notifications it writes to the device
+unsafe impl Send for SessionPtr {}
+unsafe impl Sync for SessionPtr {}
+
+// tells the kernel to drop or update what it caches of a mounted `Session`,
+// fails with ENOTCONN once the session is destroyed
+#[derive(Clone)]
+pub struct Notifier {
+    se: Arc<RwLock<SessionPtr>>,
+}
+
+impl Notifier {
+    pub(crate) fn new(se: Arc<RwLock<SessionPtr>>) -> Self {
+        Self { se }
+    }
+
+    // a negative `offset` only drops the attributes, a `len` of 0 means up to the end
+    pub fn inval_inode(&self, ino: u64, offset: i64, len: i64) -> Result<(), Neg> {
+        self.with(|se| unsafe {
+            fuse::fuse_lowlevel_notify_inval_inode(se, ino, offset as _, len as _)
+        })
+    }
+
+    pub fn inval_entry(&self, parent: u64, name: &str) -> Result<(), Neg> {
+        self.with(|se| unsafe {
+            fuse::fuse_lowlevel_notify_inval_entry(se, parent, name.as_ptr().cast(), name.len())
+        })
+    }
+
+    // like `inval_entry`, also dropping the dentry of `child` if it is still in use
+    pub fn delete(&self, parent: u64, child: u64, name: &str) -> Result<(), Neg> {
+        self.with(|se| unsafe {
+            fuse::fuse_lowlevel_notify_delete(se, parent, child, name.as_ptr().cast(), name.len())
+        })
+    }
+
+    // requires libfuse 3.16, drops the entry like the timeout of its lookup did
+    #[cfg(feature = "fuse_3_16")]
+    pub fn expire_entry(&self, parent: u64, name: &str) -> Result<(), Neg> {
+        self.with(|se| unsafe {
+            fuse::fuse_lowlevel_notify_expire_entry(se, parent, name.as_ptr().cast(), name.len())
+        })
+    }
+
+    // puts `data` in the page cache of `ino`, growing its size if needed
+    pub fn store(&self, ino: u64, offset: u64, data: &[u8]) -> Result<(), Neg> {
+        let mut bufv = fuse::fuse_bufvec {
+            count: 1,
+            idx: 0,
+            off: 0,
+            buf: [fuse::fuse_buf {
+                size: data.len(),
+                flags: 0,
+                mem: data.as_ptr() as *mut c_void,
+                fd: -1,
+                pos: 0,
+            }],
+        };
+
+        let offset = unwrap!(offset.try_into());
+
+        self.with(|se| unsafe { fuse::fuse_lowlevel_notify_store(se, ino, offset, &mut bufv, 0) })
+    }
+
+    // asks for the cached data of `ino`, answered by `LowLevelOperations::retrieve_reply`
+    pub fn retrieve(&self, ino: u64, size: usize, offset: u64, cookie: u64) -> Result<(), Neg> {
+        let offset = unwrap!(offset.try_into());
+        let cookie = cookie as usize as *mut c_void;
+
+        self.with(|se| unsafe {
+            fuse::fuse_lowlevel_notify_retrieve(se, ino, size, offset, cookie)
+        })
+    }
+
+    fn with<F: FnOnce(*mut fuse::fuse_session) -> c_int>(&self, f: F) -> Result<(), Neg> {
+        let se = unwrap!(self.se.read());
+
+        if se.0.is_null() {
+            return Err(neg!(-ENOTCONN));
+        }
+
+        result(f(se.0))
+    }
+}
+
+
+fn result(err: c_int) -> Result<(), Neg> {
+    match Neg::new(err) {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
@@ -1,13 +1,19 @@
 use std::sync::Once;
-use std::ffi::{ CStr, CString };
+#[cfg(not(feature = "fuse2"))]
+use std::sync::atomic::Ordering;
+use std::ffi::CString;
 use std::convert::TryInto;
 
 use libc::{ c_int, c_uint, c_char, c_void };
-use libc::ENOSYS;
+use libc::{ EINVAL, ENOSYS, EOPNOTSUPP };
 
 use unwrap::unwrap;
 
-use crate::{ fuse, Neg, neg };
+use crate::{ buf, fuse, xattr, Neg, neg };
+#[cfg(not(feature = "fuse2"))]
+use crate::notify;
+use crate::{ Buf, Extents, FallocateFlags, FlockOp, IoctlCmd, IoctlDir, IoctlFlags, LockCmd };
+use crate::{ PollHandle, PosixLock, ReadDir, RenameFlags, Whence, WriteBuf, XattrFlags };
 
 
 macro_rules! op_method {
@@ -27,6 +33,7 @@ macro_rules! op_result {
 }
 
 #[allow(unused_variables)]
+#[allow(clippy::too_many_arguments)]
 pub trait Operations {
     op_method! { getattr;
         path: &str,
@@ -41,7 +48,7 @@ pub trait Operations {
     op_method! { unlink ; path: &str }
     op_method! { rmdir  ; path: &str }
     op_method! { symlink; from: &str, to: &str }
-    op_method! { rename ; from: &str, to: &str, flags: c_uint }
+    op_method! { rename ; from: &str, to: &str, flags: RenameFlags }
     op_method! { link   ; from: &str, to: &str }
     op_method! { chmod  ; path: &str, mode: fuse::mode_t, fi: Option<&mut fuse::fuse_file_info> }
 
@@ -55,53 +62,82 @@ pub trait Operations {
     op_method! { truncate; path: &str, size: fuse::off_t, fi: Option<&mut fuse::fuse_file_info> }
     op_method! { open    ; path: &str, fi: &mut fuse::fuse_file_info }
 
-    op_method! { read;
+    // register `read_buf` and `write_buf` with libfuse; otherwise `read` and `write`
+    // are called with libfuse's own buffers
+    fn use_buf(&self) -> bool { false }
+
+    fn read(&mut self,
         path: &str,
-        filler: &mut dyn FnMut(&[u8]) -> Result<usize, ()>,
-        size: usize,
+        buf: &mut [u8],
         offset: fuse::off_t,
-        fi: Option<&mut fuse::fuse_file_info>
+        fi: Option<&mut fuse::fuse_file_info>) -> Result<usize, Neg>
+    {
+        let data = self.read_vec(path, buf.len(), offset, fi)?;
+        let len = data.len().min(buf.len());
+
+        buf[..len].copy_from_slice(&data[..len]);
+
+        Ok(len)
     }
 
+    fn read_vec(&mut self,
+        path: &str,
+        size: usize,
+        offset: fuse::off_t,
+        fi: Option<&mut fuse::fuse_file_info>) -> Result<Vec<u8>, Neg> { Err(neg!(-ENOSYS)) }
+
     fn write(&mut self,
         path: &str,
         buf: &[u8],
         offset: fuse::off_t,
         fi: Option<&mut fuse::fuse_file_info>) -> Result<usize, Neg> { Err(neg!(-ENOSYS)) }
 
+    fn read_buf(&mut self,
+        path: &str,
+        size: usize,
+        offset: fuse::off_t,
+        fi: Option<&mut fuse::fuse_file_info>) -> Result<Vec<Buf>, Neg>
+    {
+        let mut data = vec![0u8; size];
+
+        let len = self.read(path, &mut data, offset, fi)?;
+        data.truncate(len);
+
+        Ok(vec![Buf::Mem(data)])
+    }
+
+    fn write_buf(&mut self,
+        path: &str,
+        buf: WriteBuf<'_>,
+        offset: fuse::off_t,
+        fi: Option<&mut fuse::fuse_file_info>) -> Result<usize, Neg>
+    {
+        let mut buf = buf;
+        let data = buf.to_vec()?;
+
+        self.write(path, &data, offset, fi)
+    }
+
     op_method! { statfs; path: &str, stbuf: &mut fuse::statvfs }
     op_method! { flush ; path: &str, fi: &mut fuse::fuse_file_info }
 
     fn release(&mut self, path: &str, fi: &mut fuse::fuse_file_info) { }
 
     op_method! { fsync   ; path: &str, isdatasync: bool, fi: &mut fuse::fuse_file_info }
-    op_method! { setxattr; path: &str, name: &str, value: &[u8], flags: c_int }
+    op_method! { setxattr; path: &str, name: &str, value: &[u8], flags: XattrFlags }
 
-    op_method! { getxattr;
-        path: &str,
-        name: &str,
-        filler: &mut dyn FnMut(&[u8]) -> Result<usize, ()>,
-        size: usize
-    }
-
-    op_method! { listxattr;
-        path: &str,
-        filler: &mut dyn FnMut(&[u8]) -> Result<usize, ()>,
-        size: usize
-    }
+    fn getxattr(&mut self, path: &str, name: &str) -> Result<Vec<u8>, Neg> { Err(neg!(-ENOSYS)) }
+    fn listxattr(&mut self, path: &str) -> Result<Vec<String>, Neg> { Err(neg!(-ENOSYS)) }
 
     op_method! { removexattr; path: &str, name: &str }
 
     fn opendir(&mut self, path: &str, fi: &mut fuse::fuse_file_info) -> Result<(), Neg> { Ok(()) }
 
-    op_method! { readdir;
+    fn readdir(&mut self,
         path: &str,
-        filler: &dyn Fn(
-            &str, Option<&fuse::stat>, fuse::off_t, fuse::fuse_fill_dir_flags) -> Result<(), ()>,
-        offset: fuse::off_t,
+        offset: u64,
         fi: &mut fuse::fuse_file_info,
-        flags: fuse::fuse_readdir_flags
-    }
+        plus: bool) -> Result<ReadDir<'_>, Neg> { Err(neg!(-ENOSYS)) }
 
     fn releasedir(&mut self,
         path: &str,
@@ -117,8 +153,8 @@ pub trait Operations {
     op_method! { lock;
         path: &str,
         fi: &mut fuse::fuse_file_info,
-        cmd: c_int,
-        lock: &mut fuse::flock
+        cmd: LockCmd,
+        lock: &mut PosixLock
     }
 
     op_method! { utimens;
@@ -131,25 +167,26 @@ pub trait Operations {
 
     op_method! { ioctl;
         path: &str,
-        cmd: c_uint,
-        arg: *mut c_void,
+        cmd: IoctlCmd,
+        arg: usize,
         fi: Option<&mut fuse::fuse_file_info>,
-        flags: c_uint,
-        data: *mut c_void
+        flags: IoctlFlags,
+        input: &[u8],
+        output: &mut [u8]
     }
 
     op_method! { poll;
         path: &str,
         fi: &mut fuse::fuse_file_info,
-        ph: Option<&mut fuse::fuse_pollhandle>,
+        ph: Option<PollHandle>,
         reventsp: &mut c_uint
     }
 
-    op_method! { flock; path: &str, fi: &mut fuse::fuse_file_info, op: c_int }
+    op_method! { flock; path: &str, fi: &mut fuse::fuse_file_info, op: FlockOp, nonblock: bool }
 
     op_method! { fallocate;
         path: &str,
-        mode: c_int,
+        mode: FallocateFlags,
         offset: fuse::off_t,
         length: fuse::off_t,
         fi: &mut fuse::fuse_file_info
@@ -168,8 +205,18 @@ pub trait Operations {
     fn lseek(&mut self,
         path: &str,
         off: fuse::off_t,
-        whence: c_int,
-        fi: Option<&mut fuse::fuse_file_info>) -> Result<u64, Neg> { Err(neg!(-ENOSYS)) }
+        whence: Whence,
+        fi: Option<&mut fuse::fuse_file_info>) -> Result<u64, Neg>
+    {
+        match whence {
+            Whence::Data | Whence::Hole => self.extents(path, fi)?.seek(off, whence),
+            _ => Err(neg!(-EINVAL)),
+        }
+    }
+
+    fn extents(&mut self,
+        path: &str,
+        fi: Option<&mut fuse::fuse_file_info>) -> Result<Extents, Neg> { Err(neg!(-ENOSYS)) }
 }
 
 
@@ -179,9 +226,9 @@ static INIT: Once = Once::new();
 pub fn set_operations<T: 'static + Operations>(ops: T) -> fuse::fuse_operations {
     unsafe {
         INIT.call_once(|| USER_OPERATIONS = Some(Box::new(ops)));
-    }
 
-    fuse_operations_new()
+        fuse_operations_new(unwrap!(USER_OPERATIONS.as_ref()).use_buf())
+    }
 }
 
 macro_rules! op {
@@ -190,43 +237,23 @@ macro_rules! op {
     };
 }
 
-
-macro_rules! ptr_str {
-    ( $ptr:expr ) => {
-        match CStr::from_ptr($ptr).to_str() {
-            Ok(x) => x,
-            Err(e) => panic!("convert '*const c_char' to '&str' failed: {:?}", e),
-        }
-    };
+#[cfg(not(feature = "fuse2"))]
+unsafe extern "C" fn getattr(
+    path: *const c_char,
+    stbuf: *mut fuse::stat,
+    fi: *mut fuse::fuse_file_info) -> c_int
+{
+    op_result!(op!(getattr, ptr_str!(path), ptr_mut!(stbuf), fi.as_mut()))
 }
 
-macro_rules! ptr_mut {
-    ( $ptr:expr ) => {
-        match $ptr.as_mut() {
-            Some(x) => x,
-            None => panic!("try to convert a null ptr to mutable reference"),
-        }
-    };
+// libfuse 2 asks `fgetattr` for open files instead
+#[cfg(feature = "fuse2")]
+unsafe extern "C" fn getattr(path: *const c_char, stbuf: *mut fuse::stat) -> c_int {
+    op_result!(op!(getattr, ptr_str!(path), ptr_mut!(stbuf), None))
 }
 
-macro_rules! filler_mut {
-    ( $buf:ident, $size:expr, $index:ident ) => {
-        &mut |src| {
-            let len = src.len();
-
-            if len <= $size - $index {
-                $buf.add($index).copy_from_nonoverlapping(src.as_ptr().cast(), len);
-                $index += len;
-
-                Ok($index)
-            } else {
-                Err(())
-            }
-        }
-    }
-}
-
-unsafe extern "C" fn getattr(
+#[cfg(feature = "fuse2")]
+unsafe extern "C" fn fgetattr(
     path: *const c_char,
     stbuf: *mut fuse::stat,
     fi: *mut fuse::fuse_file_info) -> c_int
@@ -270,14 +297,25 @@ unsafe extern "C" fn symlink(from: *const c_char, to: *const c_char) -> c_int {
     op_result!(op!(symlink, ptr_str!(from), ptr_str!(to)))
 }
 
+#[cfg(not(feature = "fuse2"))]
 unsafe extern "C" fn rename(from: *const c_char, to: *const c_char, flags: c_uint) -> c_int {
-    op_result!(op!(rename, ptr_str!(from), ptr_str!(to), flags))
+    match RenameFlags::from_bits(flags) {
+        Some(flags) => op_result!(op!(rename, ptr_str!(from), ptr_str!(to), flags)),
+        None => -EINVAL,
+    }
+}
+
+// libfuse 2 has no `renameat2` flags
+#[cfg(feature = "fuse2")]
+unsafe extern "C" fn rename(from: *const c_char, to: *const c_char) -> c_int {
+    op_result!(op!(rename, ptr_str!(from), ptr_str!(to), RenameFlags::empty()))
 }
 
 unsafe extern "C" fn link(from: *const c_char, to: *const c_char) -> c_int {
     op_result!(op!(link, ptr_str!(from), ptr_str!(to)))
 }
 
+#[cfg(not(feature = "fuse2"))]
 unsafe extern "C" fn chmod(
     path: *const c_char,
     mode: fuse::mode_t,
@@ -286,6 +324,12 @@ unsafe extern "C" fn chmod(
     op_result!(op!(chmod, ptr_str!(path), mode, fi.as_mut()))
 }
 
+#[cfg(feature = "fuse2")]
+unsafe extern "C" fn chmod(path: *const c_char, mode: fuse::mode_t) -> c_int {
+    op_result!(op!(chmod, ptr_str!(path), mode, None))
+}
+
+#[cfg(not(feature = "fuse2"))]
 unsafe extern "C" fn chown(
     path: *const c_char,
     uid: fuse::uid_t,
@@ -295,6 +339,12 @@ unsafe extern "C" fn chown(
     op_result!(op!(chown, ptr_str!(path), uid, gid, fi.as_mut()))
 }
 
+#[cfg(feature = "fuse2")]
+unsafe extern "C" fn chown(path: *const c_char, uid: fuse::uid_t, gid: fuse::gid_t) -> c_int {
+    op_result!(op!(chown, ptr_str!(path), uid, gid, None))
+}
+
+#[cfg(not(feature = "fuse2"))]
 unsafe extern "C" fn truncate(
     path: *const c_char,
     size: fuse::off_t,
@@ -303,6 +353,21 @@ unsafe extern "C" fn truncate(
     op_result!(op!(truncate, ptr_str!(path), size, fi.as_mut()))
 }
 
+// libfuse 2 asks `ftruncate` for open files instead
+#[cfg(feature = "fuse2")]
+unsafe extern "C" fn truncate(path: *const c_char, size: fuse::off_t) -> c_int {
+    op_result!(op!(truncate, ptr_str!(path), size, None))
+}
+
+#[cfg(feature = "fuse2")]
+unsafe extern "C" fn ftruncate(
+    path: *const c_char,
+    size: fuse::off_t,
+    fi: *mut fuse::fuse_file_info) -> c_int
+{
+    op_result!(op!(truncate, ptr_str!(path), size, fi.as_mut()))
+}
+
 unsafe extern "C" fn open(path: *const c_char, fi: *mut fuse::fuse_file_info) -> c_int {
     op_result!(op!(open, ptr_str!(path), ptr_mut!(fi)))
 }
@@ -314,10 +379,10 @@ unsafe extern "C" fn read(
     offset: fuse::off_t,
     fi: *mut fuse::fuse_file_info) -> c_int
 {
-    let mut index = 0usize;
+    let buf = std::slice::from_raw_parts_mut(buf.cast(), size);
 
-    match op!(read, ptr_str!(path), filler_mut!(buf, size, index), size, offset, fi.as_mut()) {
-        Ok(_) => unwrap!(index.try_into()),
+    match op!(read, ptr_str!(path), buf, offset, fi.as_mut()) {
+        Ok(x) => unwrap!(x.min(size).try_into()),
         Err(e) => e.get(),
     }
 }
@@ -341,6 +406,38 @@ unsafe extern "C" fn write(
     }
 }
 
+unsafe extern "C" fn read_buf(
+    path: *const c_char,
+    bufp: *mut *mut fuse::fuse_bufvec,
+    size: usize,
+    offset: fuse::off_t,
+    fi: *mut fuse::fuse_file_info) -> c_int
+{
+    let res = op!(read_buf, ptr_str!(path), size, offset, fi.as_mut())
+        .and_then(|x| buf::into_raw(x));
+
+    match res {
+        Ok(x) => {
+            *bufp = x;
+
+            0
+        },
+        Err(e) => e.get(),
+    }
+}
+
+unsafe extern "C" fn write_buf(
+    path: *const c_char,
+    bufv: *mut fuse::fuse_bufvec,
+    offset: fuse::off_t,
+    fi: *mut fuse::fuse_file_info) -> c_int
+{
+    match op!(write_buf, ptr_str!(path), WriteBuf::new(ptr_mut!(bufv)), offset, fi.as_mut()) {
+        Ok(x) => unwrap!(x.try_into()),
+        Err(e) => e.get(),
+    }
+}
+
 unsafe extern "C" fn statfs(path: *const c_char, stbuf: *mut fuse::statvfs) -> c_int {
     op_result!(op!(statfs, ptr_str!(path), ptr_mut!(stbuf)))
 }
@@ -370,6 +467,11 @@ unsafe extern "C" fn setxattr(
     size: usize,
     flags: c_int) -> c_int
 {
+    let flags = match XattrFlags::from_bits(flags) {
+        Some(x) => x,
+        None => return -EINVAL,
+    };
+
     op_result!(op!(setxattr,
         ptr_str!(path),
         ptr_str!(name),
@@ -383,19 +485,15 @@ unsafe extern "C" fn getxattr(
     value: *mut c_char,
     size: usize) -> c_int
 {
-    let mut index = 0usize;
-
-    match op!(getxattr, ptr_str!(path), ptr_str!(name), filler_mut!(value, size, index), size) {
-        Ok(_) => unwrap!(index.try_into()),
+    match op!(getxattr, ptr_str!(path), ptr_str!(name)) {
+        Ok(x) => xattr::reply(&x, value, size),
         Err(e) => e.get(),
     }
 }
 
 unsafe extern "C" fn listxattr(path: *const c_char, list: *mut c_char, size: usize) -> c_int {
-    let mut index = 0usize;
-
-    match op!(listxattr, ptr_str!(path), filler_mut!(list, size, index), size) {
-        Ok(_) => unwrap!(index.try_into()),
+    match op!(listxattr, ptr_str!(path)) {
+        Ok(x) => xattr::reply(&xattr::name_list(&x), list, size),
         Err(e) => e.get(),
     }
 }
@@ -408,6 +506,7 @@ unsafe extern "C" fn opendir(path: *const c_char, fi: *mut fuse::fuse_file_info)
     op_result!(op!(opendir, ptr_str!(path), ptr_mut!(fi)))
 }
 
+#[cfg(not(feature = "fuse2"))]
 unsafe extern "C" fn readdir(
     path: *const c_char,
     buf: *mut c_void,
@@ -416,31 +515,34 @@ unsafe extern "C" fn readdir(
     fi: *mut fuse::fuse_file_info,
     flags: fuse::fuse_readdir_flags) -> c_int
 {
-    let filler = unwrap!(filler);
+    let plus = flags & fuse::fuse_readdir_flags_FUSE_READDIR_PLUS != 0;
 
-    op_result!(op!(readdir,
-        ptr_str!(path),
-        &|name, stbuf, offset, flags| {
-            let name = unwrap!(CString::new(name));
-
-            let stbuf = if let Some(x) = stbuf {
-                x
-            } else {
-                std::ptr::null()
-            };
-
-            let res = filler(buf, name.as_ptr(), stbuf, offset, flags);
-
-            if res == 0 {
-                Ok(())
-            } else {
-                assert_eq!(res, 1);
-                Err(())
-            }
+    match op!(readdir, ptr_str!(path), unwrap!(offset.try_into()), ptr_mut!(fi), plus) {
+        Ok(x) => {
+            x.fill(buf, filler, offset, plus);
+
+            0
         },
-        offset,
-        ptr_mut!(fi),
-        flags))
+        Err(e) => e.get(),
+    }
+}
+
+#[cfg(feature = "fuse2")]
+unsafe extern "C" fn readdir(
+    path: *const c_char,
+    buf: *mut c_void,
+    filler: fuse::fuse_fill_dir_t,
+    offset: fuse::off_t,
+    fi: *mut fuse::fuse_file_info) -> c_int
+{
+    match op!(readdir, ptr_str!(path), unwrap!(offset.try_into()), ptr_mut!(fi), false) {
+        Ok(x) => {
+            x.fill(buf, filler, offset, false);
+
+            0
+        },
+        Err(e) => e.get(),
+    }
 }
 
 unsafe extern "C" fn releasedir(path: *const c_char, fi: *mut fuse::fuse_file_info) -> c_int {
@@ -455,13 +557,32 @@ unsafe extern "C" fn fsyncdir(
     op_result!(op!(fsyncdir, ptr_str!(path), datasync, ptr_mut!(fi)))
 }
 
+#[cfg(not(feature = "fuse2"))]
 unsafe extern "C" fn init(
     info: *mut fuse::fuse_conn_info,
     conf: *mut fuse::fuse_config) -> *mut c_void
 {
     op!(init, ptr_mut!(info), ptr_mut!(conf));
 
-    std::ptr::null_mut()
+    // keep the data given to `fuse_main_real` as the filesystem private data
+    match fuse::fuse_get_context().as_ref() {
+        Some(ctx) => {
+            notify::FUSE.store(ctx.fuse, Ordering::Release);
+
+            ctx.private_data
+        },
+        None => std::ptr::null_mut(),
+    }
+}
+
+#[cfg(feature = "fuse2")]
+unsafe extern "C" fn init(info: *mut fuse::fuse_conn_info) -> *mut c_void {
+    op!(init, ptr_mut!(info), &mut fuse::fuse_config::default());
+
+    match fuse::fuse_get_context().as_ref() {
+        Some(ctx) => ctx.private_data,
+        None => std::ptr::null_mut(),
+    }
 }
 
 unsafe extern "C" fn access(path: *const c_char, mask: c_int) -> c_int {
@@ -482,9 +603,27 @@ unsafe extern "C" fn lock(
     cmd: c_int,
     lock: *mut fuse::flock) -> c_int
 {
-    op_result!(op!(lock, ptr_str!(path), ptr_mut!(fi), cmd, ptr_mut!(lock)))
+    let fi = ptr_mut!(fi);
+    let lock = ptr_mut!(lock);
+
+    let cmd = LockCmd::from_raw(cmd);
+    let posix_lock = PosixLock::from_flock(lock, fi.lock_owner);
+
+    let (cmd, mut posix_lock) = match (cmd, posix_lock) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return -EINVAL,
+    };
+
+    let res = op!(lock, ptr_str!(path), fi, cmd, &mut posix_lock);
+
+    if res.is_ok() {
+        posix_lock.fill(lock);
+    }
+
+    op_result!(res)
 }
 
+#[cfg(not(feature = "fuse2"))]
 unsafe extern "C" fn utimens(
     path: *const c_char,
     ts: *const fuse::timespec,
@@ -493,19 +632,53 @@ unsafe extern "C" fn utimens(
     op_result!(op!(utimens, ptr_str!(path), std::slice::from_raw_parts(ts, 2), fi.as_mut()))
 }
 
+#[cfg(feature = "fuse2")]
+unsafe extern "C" fn utimens(path: *const c_char, ts: *const fuse::timespec) -> c_int {
+    op_result!(op!(utimens, ptr_str!(path), std::slice::from_raw_parts(ts, 2), None))
+}
+
 unsafe extern "C" fn bmap(path: *const c_char, blocksize: usize, idx: *mut u64) -> c_int {
     op_result!(op!(bmap, ptr_str!(path), blocksize, ptr_mut!(idx)))
 }
 
+// the command is signed in libfuse 2 and below FUSE_USE_VERSION 35
+#[cfg(any(feature = "fuse2", not(feature = "fuse_3_12")))]
+type RawIoctlCmd = c_int;
+#[cfg(all(not(feature = "fuse2"), feature = "fuse_3_12"))]
+type RawIoctlCmd = c_uint;
+
 unsafe extern "C" fn ioctl(
     path: *const c_char,
-    cmd: c_uint,
+    cmd: RawIoctlCmd,
     arg: *mut c_void,
     fi: *mut fuse::fuse_file_info,
     flags: c_uint,
     data: *mut c_void) -> c_int
 {
-    op_result!(op!(ioctl, ptr_str!(path), cmd, arg, fi.as_mut(), flags, data))
+    let cmd = IoctlCmd(cmd as c_uint);
+    let dir = cmd.dir();
+
+    // libfuse passes a single buffer, the input is overwritten by the output
+    let input = if dir.contains(IoctlDir::WRITE) && !data.is_null() {
+        std::slice::from_raw_parts(data.cast::<u8>(), cmd.size()).to_vec()
+    } else {
+        Vec::new()
+    };
+
+    let output: &mut [u8] = if dir.contains(IoctlDir::READ) && !data.is_null() {
+        std::slice::from_raw_parts_mut(data.cast(), cmd.size())
+    } else {
+        &mut []
+    };
+
+    op_result!(op!(ioctl,
+        ptr_str!(path),
+        cmd,
+        arg as usize,
+        fi.as_mut(),
+        IoctlFlags::from_bits_truncate(flags),
+        &input,
+        output))
 }
 
 unsafe extern "C" fn poll(
@@ -514,11 +687,16 @@ unsafe extern "C" fn poll(
     ph: *mut fuse::fuse_pollhandle,
     reventsp: *mut c_uint) -> c_int
 {
-    op_result!(op!(poll, ptr_str!(path), ptr_mut!(fi), ph.as_mut(), ptr_mut!(reventsp)))
+    let ph = PollHandle::from_raw(ph);
+
+    op_result!(op!(poll, ptr_str!(path), ptr_mut!(fi), ph, ptr_mut!(reventsp)))
 }
 
 unsafe extern "C" fn flock(path: *const c_char, fi: *mut fuse::fuse_file_info, op: c_int) -> c_int {
-    op_result!(op!(flock, ptr_str!(path), ptr_mut!(fi), op))
+    match FlockOp::from_raw(op) {
+        Some((op, nonblock)) => op_result!(op!(flock, ptr_str!(path), ptr_mut!(fi), op, nonblock)),
+        None => -EINVAL,
+    }
 }
 
 unsafe extern "C" fn fallocate(
@@ -528,9 +706,13 @@ unsafe extern "C" fn fallocate(
     length: fuse::off_t,
     fi: *mut fuse::fuse_file_info) -> c_int
 {
-    op_result!(op!(fallocate, ptr_str!(path), mode, offset, length, ptr_mut!(fi)))
+    match FallocateFlags::from_bits(mode) {
+        Some(x) => op_result!(op!(fallocate, ptr_str!(path), x, offset, length, ptr_mut!(fi))),
+        None => -EOPNOTSUPP,
+    }
 }
 
+#[cfg(not(feature = "fuse2"))]
 unsafe extern "C" fn copy_file_range(
     path_in: *const c_char, fi_in: *mut fuse::fuse_file_info, off_in: fuse::off_t,
     path_out: *const c_char, fi_out: *mut fuse::fuse_file_info, off_out: fuse::off_t,
@@ -547,19 +729,26 @@ unsafe extern "C" fn copy_file_range(
     }
 }
 
+#[cfg(not(feature = "fuse2"))]
 unsafe extern "C" fn lseek(
     path: *const c_char,
     off: fuse::off_t,
     whence: c_int,
     fi: *mut fuse::fuse_file_info) -> fuse::off_t
 {
+    let whence = match Whence::from_raw(whence) {
+        Some(x) => x,
+        None => return (-EINVAL).into(),
+    };
+
     match op!(lseek, ptr_str!(path), off, whence, fi.as_mut()) {
         Ok(x) => unwrap!(x.try_into()),
         Err(e) => unwrap!(e.get().try_into()),
     }
 }
 
-fn fuse_operations_new() -> fuse::fuse_operations {
+#[cfg(not(feature = "fuse2"))]
+fn fuse_operations_new(use_buf: bool) -> fuse::fuse_operations {
     fuse::fuse_operations {
         getattr: Some(getattr),
         readlink: Some(readlink),
@@ -597,11 +786,64 @@ fn fuse_operations_new() -> fuse::fuse_operations {
         bmap: Some(bmap),
         ioctl: Some(ioctl),
         poll: Some(poll),
-        write_buf: None,
-        read_buf: None,
+        write_buf: if use_buf { Some(write_buf) } else { None },
+        read_buf: if use_buf { Some(read_buf) } else { None },
         flock: Some(flock),
         fallocate: Some(fallocate),
         copy_file_range: Some(copy_file_range),
         lseek: Some(lseek),
     }
 }
+
+#[cfg(feature = "fuse2")]
+fn fuse_operations_new(use_buf: bool) -> fuse::fuse_operations {
+    let mut ops = fuse::fuse_operations {
+        getattr: Some(getattr),
+        readlink: Some(readlink),
+        mknod: Some(mknod),
+        mkdir: Some(mkdir),
+        unlink: Some(unlink),
+        rmdir: Some(rmdir),
+        symlink: Some(symlink),
+        rename: Some(rename),
+        link: Some(link),
+        chmod: Some(chmod),
+        chown: Some(chown),
+        truncate: Some(truncate),
+        open: Some(open),
+        read: Some(read),
+        write: Some(write),
+        statfs: Some(statfs),
+        flush: Some(flush),
+        release: Some(release),
+        fsync: Some(fsync),
+        setxattr: Some(setxattr),
+        getxattr: Some(getxattr),
+        listxattr: Some(listxattr),
+        removexattr: Some(removexattr),
+        opendir: Some(opendir),
+        readdir: Some(readdir),
+        releasedir: Some(releasedir),
+        fsyncdir: Some(fsyncdir),
+        init: Some(init),
+        access: Some(access),
+        create: Some(create),
+        ftruncate: Some(ftruncate),
+        fgetattr: Some(fgetattr),
+        lock: Some(lock),
+        utimens: Some(utimens),
+        bmap: Some(bmap),
+        ioctl: Some(ioctl),
+        poll: Some(poll),
+        write_buf: if use_buf { Some(write_buf) } else { None },
+        read_buf: if use_buf { Some(read_buf) } else { None },
+        flock: Some(flock),
+        fallocate: Some(fallocate),
+        ..Default::default()
+    };
+
+    // `utimens` gets UTIME_NOW and UTIME_OMIT as with libfuse 3
+    ops.set_flag_utime_omit_ok(1);
+
+    ops
+}
@@ -0,0 +1,133 @@
+use libc::{ c_int, R_OK, W_OK, X_OK, O_ACCMODE, O_RDONLY, O_WRONLY, O_RDWR, O_TRUNC, S_ISVTX };
+use libc::EACCES;
+
+use crate::{ fuse, neg, Context, FileAttr, FileType, Neg };
+
+
+pub fn check_access(
+    attr: &FileAttr,
+    ctx: &Context,
+    groups: &[fuse::gid_t],
+    mask: c_int) -> Result<(), Neg>
+{
+    let mask = mask & (R_OK | W_OK | X_OK);
+
+    if mask == 0 {
+        return Ok(());
+    }
+
+    if ctx.uid == 0 {
+        // root may execute only if someone can, or search any directory
+        if mask & X_OK != 0 && attr.kind != FileType::Directory && attr.perm & 0o111 == 0 {
+            return Err(neg!(-EACCES));
+        }
+
+        return Ok(());
+    }
+
+    let perm = c_int::from(attr.perm);
+
+    let bits = if ctx.uid == attr.uid {
+        perm >> 6
+    } else if ctx.gid == attr.gid || groups.contains(&attr.gid) {
+        perm >> 3
+    } else {
+        perm
+    };
+
+    if mask & !bits & 0o7 == 0 {
+        Ok(())
+    } else {
+        Err(neg!(-EACCES))
+    }
+}
+
+// removing or renaming `attr` inside a sticky `dir` needs to own either of them
+pub fn check_sticky(dir: &FileAttr, attr: &FileAttr, ctx: &Context) -> Result<(), Neg> {
+    if fuse::mode_t::from(dir.perm) & S_ISVTX == 0
+        || ctx.uid == 0
+        || ctx.uid == dir.uid
+        || ctx.uid == attr.uid
+    {
+        Ok(())
+    } else {
+        Err(neg!(-EACCES))
+    }
+}
+
+// the access mask `open` and `create` need for the given `fuse_file_info::flags`
+pub fn open_mask(flags: c_int) -> c_int {
+    let mask = match flags & O_ACCMODE {
+        O_RDONLY => R_OK,
+        O_WRONLY => W_OK,
+        O_RDWR => R_OK | W_OK,
+        _ => R_OK | W_OK,
+    };
+
+    if flags & O_TRUNC != 0 {
+        mask | W_OK
+    } else {
+        mask
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    fn attr(kind: FileType, perm: u16, uid: fuse::uid_t, gid: fuse::gid_t) -> FileAttr {
+        FileAttr {
+            ino: 2,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            kind,
+            perm,
+            nlink: 1,
+            uid,
+            gid,
+            rdev: 0,
+            blksize: 4096,
+        }
+    }
+
+    fn ctx(uid: fuse::uid_t, gid: fuse::gid_t) -> Context {
+        Context { uid, gid, pid: 1, umask: 0o022, private_data: None }
+    }
+
+    #[test]
+    fn owner_group_other() {
+        let file = attr(FileType::RegularFile, 0o640, 1000, 100);
+
+        assert!(check_access(&file, &ctx(1000, 1000), &[], R_OK | W_OK).is_ok());
+        assert!(check_access(&file, &ctx(1001, 100), &[], R_OK).is_ok());
+        assert!(check_access(&file, &ctx(1001, 100), &[], W_OK).is_err());
+        assert!(check_access(&file, &ctx(1001, 1001), &[100], R_OK).is_ok());
+        assert!(check_access(&file, &ctx(1001, 1001), &[], R_OK).is_err());
+    }
+
+    #[test]
+    fn root() {
+        let file = attr(FileType::RegularFile, 0o600, 1000, 100);
+        let dir = attr(FileType::Directory, 0o700, 1000, 100);
+
+        assert!(check_access(&file, &ctx(0, 0), &[], R_OK | W_OK).is_ok());
+        assert!(check_access(&file, &ctx(0, 0), &[], X_OK).is_err());
+        assert!(check_access(&dir, &ctx(0, 0), &[], X_OK).is_ok());
+    }
+
+    #[test]
+    fn sticky() {
+        let dir = attr(FileType::Directory, 0o1777, 0, 0);
+        let file = attr(FileType::RegularFile, 0o666, 1000, 100);
+
+        assert!(check_sticky(&dir, &file, &ctx(1000, 100)).is_ok());
+        assert!(check_sticky(&dir, &file, &ctx(1001, 100)).is_err());
+        assert!(check_sticky(&dir, &file, &ctx(0, 0)).is_ok());
+    }
+}
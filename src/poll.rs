@@ -0,0 +1,31 @@
+use std::ptr::NonNull;
+
+use crate::{ fuse, Neg };
+
+
+// owned by the filesystem once handed to `Operations::poll`, destroyed on drop
+#[derive(Debug)]
+pub struct PollHandle(NonNull<fuse::fuse_pollhandle>);
+
+unsafe impl Send for PollHandle {}
+unsafe impl Sync for PollHandle {}
+
+impl PollHandle {
+    pub(crate) unsafe fn from_raw(ph: *mut fuse::fuse_pollhandle) -> Option<Self> {
+        NonNull::new(ph).map(Self)
+    }
+
+    // tells the kernel to poll the file again, its readiness changed
+    pub fn notify(&self) -> Result<(), Neg> {
+        match Neg::new(unsafe { fuse::fuse_notify_poll(self.0.as_ptr()) }) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for PollHandle {
+    fn drop(&mut self) {
+        unsafe { fuse::fuse_pollhandle_destroy(self.0.as_ptr()); }
+    }
+}
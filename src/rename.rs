@@ -0,0 +1,119 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use bitflags::bitflags;
+
+use libc::{ c_uint, c_long, AT_FDCWD, SYS_renameat2 };
+use libc::{ EINVAL, ENOSYS };
+
+use crate::{ Neg, neg };
+
+
+bitflags! {
+    pub struct RenameFlags: c_uint {
+        const NOREPLACE = 1 << 0;
+        const EXCHANGE = 1 << 1;
+        const WHITEOUT = 1 << 2;
+    }
+}
+
+
+// renames a file of a path-based backend honoring `flags` atomically through renameat2,
+// fails with EINVAL where the backing filesystem lacks a flag and ENOSYS where the kernel does
+pub fn rename<P, Q>(from: P, to: Q, flags: RenameFlags) -> Result<(), Neg>
+    where P: AsRef<Path>,
+          Q: AsRef<Path>
+{
+    let from = CString::new(from.as_ref().as_os_str().as_bytes()).map_err(|_| neg!(-EINVAL))?;
+    let to = CString::new(to.as_ref().as_os_str().as_bytes()).map_err(|_| neg!(-EINVAL))?;
+
+    if flags.contains(RenameFlags::NOREPLACE | RenameFlags::EXCHANGE) {
+        return Err(neg!(-EINVAL));
+    }
+
+    let res = unsafe {
+        libc::syscall(SYS_renameat2,
+            c_long::from(AT_FDCWD), from.as_ptr(),
+            c_long::from(AT_FDCWD), to.as_ptr(),
+            c_long::from(flags.bits()))
+    };
+
+    if res == 0 {
+        return Ok(());
+    }
+
+    match errno() {
+        // without flags there is nothing rename can't do
+        ENOSYS if flags.is_empty() => match unsafe { libc::rename(from.as_ptr(), to.as_ptr()) } {
+            0 => Ok(()),
+            _ => Err(neg!(-errno())),
+        },
+        e => Err(neg!(-e)),
+    }
+}
+
+fn errno() -> i32 {
+    std::io::Error::last_os_error().raw_os_error().unwrap_or(EINVAL)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use libc::EEXIST;
+
+    use unwrap::unwrap;
+
+    use super::*;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("libfuse-sys-{}-{}", name, std::process::id()));
+
+        drop(fs::remove_dir_all(&dir));
+        unwrap!(fs::create_dir(&dir));
+
+        dir
+    }
+
+    #[test]
+    fn noreplace() {
+        let dir = dir("noreplace");
+        let (a, b, c) = (dir.join("a"), dir.join("b"), dir.join("c"));
+
+        unwrap!(fs::write(&a, "a"));
+        unwrap!(fs::write(&b, "b"));
+
+        assert_eq!(rename(&a, &b, RenameFlags::NOREPLACE), Err(neg!(-EEXIST)));
+        assert_eq!(unwrap!(fs::read_to_string(&a)), "a");
+        assert_eq!(unwrap!(fs::read_to_string(&b)), "b");
+
+        assert_eq!(rename(&a, &c, RenameFlags::NOREPLACE), Ok(()));
+        assert!(!a.exists());
+        assert_eq!(unwrap!(fs::read_to_string(&c)), "a");
+
+        unwrap!(fs::remove_dir_all(&dir));
+    }
+
+    #[test]
+    fn exchange() {
+        let dir = dir("exchange");
+        let (a, b) = (dir.join("a"), dir.join("b"));
+
+        unwrap!(fs::write(&a, "a"));
+        unwrap!(fs::write(&b, "b"));
+
+        assert_eq!(rename(&a, &b, RenameFlags::EXCHANGE), Ok(()));
+        assert_eq!(unwrap!(fs::read_to_string(&a)), "b");
+        assert_eq!(unwrap!(fs::read_to_string(&b)), "a");
+
+        let both = RenameFlags::NOREPLACE | RenameFlags::EXCHANGE;
+
+        assert_eq!(rename(&a, &b, both), Err(neg!(-EINVAL)));
+        assert_eq!(rename("a\0b", &b, RenameFlags::empty()), Err(neg!(-EINVAL)));
+
+        unwrap!(fs::remove_dir_all(&dir));
+    }
+}
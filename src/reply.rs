@@ -0,0 +1,358 @@
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::time::Duration;
+
+use libc::{ c_int, c_char };
+use libc::{ EIO, ERANGE };
+
+use unwrap::unwrap;
+
+use crate::{ fuse, interrupt, xattr, Neg, neg };
+use crate::{ DirEntry, DirEntryPlus, Entry, FileAttr, PosixLock, ReadDir };
+
+
+// a request still to be answered, answered with EIO if dropped unanswered
+struct RawReply(fuse::fuse_req_t);
+
+// libfuse allows answering a request from any thread
+unsafe impl Send for RawReply {}
+
+impl RawReply {
+    fn new(req: fuse::fuse_req_t) -> Self {
+        Self(req)
+    }
+
+    fn send<F: FnOnce(fuse::fuse_req_t) -> c_int>(mut self, f: F) {
+        let req = std::mem::replace(&mut self.0, std::ptr::null_mut());
+
+        interrupt::forget_request(req);
+        f(req);
+    }
+
+    fn error(self, e: Neg) {
+        self.send(|req| unsafe { fuse::fuse_reply_err(req, -e.get()) });
+    }
+
+    fn ok(self) {
+        self.send(|req| unsafe { fuse::fuse_reply_err(req, 0) });
+    }
+}
+
+impl Drop for RawReply {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            interrupt::forget_request(self.0);
+            unsafe { fuse::fuse_reply_err(self.0, EIO); }
+        }
+    }
+}
+
+
+macro_rules! reply_types {
+    ( $( $name:ident ),* ) => {
+        $(
+            pub struct $name(RawReply);
+
+            impl $name {
+                pub(crate) fn new(req: fuse::fuse_req_t) -> Self {
+                    Self(RawReply::new(req))
+                }
+
+                pub fn error(self, e: Neg) {
+                    self.0.error(e);
+                }
+            }
+        )*
+    };
+}
+
+reply_types! {
+    ReplyEmpty, ReplyEntry, ReplyCreate, ReplyAttr, ReplyReadlink, ReplyOpen, ReplyData,
+    ReplyWrite, ReplyStatfs, ReplyLock, ReplyBmap, ReplyLseek, ReplyIoctl, ReplyPoll
+}
+
+impl ReplyEmpty {
+    pub fn ok(self) {
+        self.0.ok();
+    }
+}
+
+impl ReplyEntry {
+    pub fn entry(self, entry: &Entry) {
+        self.0.send(|req| unsafe { fuse::fuse_reply_entry(req, &entry.raw()) });
+    }
+}
+
+impl ReplyCreate {
+    pub fn created(self, entry: &Entry, fi: &fuse::fuse_file_info) {
+        self.0.send(|req| unsafe { fuse::fuse_reply_create(req, &entry.raw(), fi) });
+    }
+}
+
+impl ReplyAttr {
+    pub fn attr(self, attr: &FileAttr, ttl: Duration) {
+        let mut stbuf = unsafe { std::mem::MaybeUninit::<fuse::stat>::zeroed().assume_init() };
+
+        attr.fill(&mut stbuf);
+
+        self.0.send(|req| unsafe { fuse::fuse_reply_attr(req, &stbuf, ttl.as_secs_f64()) });
+    }
+}
+
+impl ReplyReadlink {
+    pub fn link(self, link: &str) {
+        let link = unwrap!(CString::new(link));
+
+        self.0.send(|req| unsafe { fuse::fuse_reply_readlink(req, link.as_ptr()) });
+    }
+}
+
+impl ReplyOpen {
+    // `fi` as set up by the filesystem, its `fh` and flags are handed back to the kernel
+    pub fn opened(self, fi: &fuse::fuse_file_info) {
+        self.0.send(|req| unsafe { fuse::fuse_reply_open(req, fi) });
+    }
+}
+
+impl ReplyData {
+    pub fn data(self, data: &[u8]) {
+        self.0.send(|req| unsafe { fuse::fuse_reply_buf(req, data.as_ptr().cast(), data.len()) });
+    }
+}
+
+impl ReplyWrite {
+    pub fn written(self, count: usize) {
+        self.0.send(|req| unsafe { fuse::fuse_reply_write(req, count) });
+    }
+}
+
+impl ReplyStatfs {
+    pub fn statfs(self, stbuf: &fuse::statvfs) {
+        self.0.send(|req| unsafe { fuse::fuse_reply_statfs(req, stbuf) });
+    }
+}
+
+impl ReplyLock {
+    // answers `LockCmd::GetLk` with the conflicting lock, or one of `LockType::Unlock`
+    pub fn locked(self, lock: &PosixLock) {
+        let mut flock = unsafe { std::mem::MaybeUninit::<fuse::flock>::zeroed().assume_init() };
+
+        lock.fill(&mut flock);
+
+        self.0.send(|req| unsafe { fuse::fuse_reply_lock(req, &flock) });
+    }
+
+    // answers `LockCmd::SetLk` and `LockCmd::SetLkw`
+    pub fn ok(self) {
+        self.0.ok();
+    }
+}
+
+impl ReplyBmap {
+    pub fn bmap(self, idx: u64) {
+        self.0.send(|req| unsafe { fuse::fuse_reply_bmap(req, idx) });
+    }
+}
+
+impl ReplyLseek {
+    pub fn offset(self, off: u64) {
+        let off = unwrap!(off.try_into());
+
+        self.0.send(|req| unsafe { fuse::fuse_reply_lseek(req, off) });
+    }
+}
+
+impl ReplyIoctl {
+    // `data` is copied out to the caller, at most the `out_size` the request was given
+    pub fn ioctl(self, result: c_int, data: &[u8]) {
+        self.0.send(|req| unsafe {
+            fuse::fuse_reply_ioctl(req, result, data.as_ptr().cast(), data.len())
+        });
+    }
+
+    // asks an unrestricted ioctl again with the caller memory given by `input` and `output`
+    pub fn retry(self, input: &[fuse::iovec], output: &[fuse::iovec]) {
+        self.0.send(|req| unsafe {
+            fuse::fuse_reply_ioctl_retry(
+                req,
+                input.as_ptr(),
+                input.len(),
+                output.as_ptr(),
+                output.len())
+        });
+    }
+}
+
+impl ReplyPoll {
+    pub fn revents(self, revents: u32) {
+        self.0.send(|req| unsafe { fuse::fuse_reply_poll(req, revents) });
+    }
+}
+
+
+// answers `getxattr` and `listxattr`, including the size probe of `size == 0`
+pub struct ReplyXattr {
+    reply: RawReply,
+    size: usize,
+}
+
+impl ReplyXattr {
+    pub(crate) fn new(req: fuse::fuse_req_t, size: usize) -> Self {
+        Self { reply: RawReply::new(req), size }
+    }
+
+    pub fn value(self, data: &[u8]) {
+        let size = self.size;
+
+        if size == 0 {
+            self.reply.send(|req| unsafe { fuse::fuse_reply_xattr(req, data.len()) });
+        } else if data.len() > size {
+            self.reply.error(neg!(-ERANGE));
+        } else {
+            self.reply.send(|req| unsafe {
+                fuse::fuse_reply_buf(req, data.as_ptr().cast(), data.len())
+            });
+        }
+    }
+
+    pub fn names(self, names: &[String]) {
+        self.value(&xattr::name_list(names));
+    }
+
+    pub fn error(self, e: Neg) {
+        self.reply.error(e);
+    }
+}
+
+
+struct DirBuf {
+    reply: RawReply,
+    buf: Vec<u8>,
+    len: usize,
+    offset: u64,
+}
+
+impl DirBuf {
+    fn new(req: fuse::fuse_req_t, size: usize, offset: u64) -> Self {
+        Self { reply: RawReply::new(req), buf: vec![0; size], len: 0, offset }
+    }
+
+    // `add` returns the size the entry needs, it is only kept if that fits
+    fn add<F>(&mut self, add: F) -> bool
+        where F: FnOnce(fuse::fuse_req_t, *mut c_char, usize) -> usize
+    {
+        let rest = self.buf.len() - self.len;
+        let n = add(self.reply.0, unsafe { self.buf.as_mut_ptr().add(self.len).cast() }, rest);
+
+        if n > rest {
+            return false;
+        }
+
+        self.len += n;
+
+        true
+    }
+
+    fn send(self) {
+        let DirBuf { reply, buf, len, .. } = self;
+
+        reply.send(|req| unsafe { fuse::fuse_reply_buf(req, buf.as_ptr().cast(), len) });
+    }
+}
+
+// `None` for a name containing a nul byte, which can't be listed
+fn c_name(name: &str) -> Option<CString> {
+    CString::new(name).ok()
+}
+
+
+// the entries of `readdir`, as many as fit in the size asked by the kernel
+pub struct ReplyDirectory(DirBuf);
+
+impl ReplyDirectory {
+    pub(crate) fn new(req: fuse::fuse_req_t, size: usize, offset: u64) -> Self {
+        Self(DirBuf::new(req, size, offset))
+    }
+
+    // `false` once full, `entry` is not added then, `offset` is where to resume after it,
+    // an entry whose name contains a nul byte is skipped
+    pub fn add(&mut self, entry: &DirEntry, offset: u64) -> bool {
+        let mut stbuf = unsafe { std::mem::MaybeUninit::<fuse::stat>::zeroed().assume_init() };
+        let name = match c_name(&entry.name) {
+            Some(x) => x,
+            None => return true,
+        };
+        let off = unwrap!(offset.try_into());
+
+        entry.fill(&mut stbuf);
+
+        self.0.add(|req, buf, size| unsafe {
+            fuse::fuse_add_direntry(req, buf, size, name.as_ptr(), &stbuf, off)
+        })
+    }
+
+    // adds the entries after the requested offset, `ReadDir::Whole` is skipped to it
+    pub fn fill(mut self, entries: ReadDir<'_>) {
+        for (off, entry) in entries.after(self.0.offset) {
+            if !self.add(&entry, off) {
+                break;
+            }
+        }
+
+        self.ok();
+    }
+
+    pub fn ok(self) {
+        self.0.send();
+    }
+
+    pub fn error(self, e: Neg) {
+        self.0.reply.error(e);
+    }
+}
+
+
+// the entries of `readdirplus`, each one added counts as a lookup except `.` and `..`
+pub struct ReplyDirectoryPlus(DirBuf);
+
+impl ReplyDirectoryPlus {
+    pub(crate) fn new(req: fuse::fuse_req_t, size: usize, offset: u64) -> Self {
+        Self(DirBuf::new(req, size, offset))
+    }
+
+    pub fn add(&mut self, entry: &DirEntryPlus, offset: u64) -> bool {
+        let mut e = entry.entry.raw();
+        let name = match c_name(&entry.name) {
+            Some(x) => x,
+            None => return true,
+        };
+        let off = unwrap!(offset.try_into());
+
+        // the kernel takes no reference on `.` and `..`
+        if entry.name == "." || entry.name == ".." {
+            e.ino = 0;
+        }
+
+        self.0.add(|req, buf, size| unsafe {
+            fuse::fuse_add_direntry_plus(req, buf, size, name.as_ptr(), &e, off)
+        })
+    }
+
+    pub fn fill(mut self, entries: ReadDir<'_, DirEntryPlus>) {
+        for (off, entry) in entries.after(self.0.offset) {
+            if !self.add(&entry, off) {
+                break;
+            }
+        }
+
+        self.ok();
+    }
+
+    pub fn ok(self) {
+        self.0.send();
+    }
+
+    pub fn error(self, e: Neg) {
+        self.0.reply.error(e);
+    }
+}
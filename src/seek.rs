@@ -0,0 +1,155 @@
+use std::convert::TryInto;
+use std::ops::Range;
+
+use libc::c_int;
+use libc::{ SEEK_SET, SEEK_CUR, SEEK_END, SEEK_DATA, SEEK_HOLE };
+use libc::{ EINVAL, ENXIO };
+
+use crate::{ fuse, Neg, neg };
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Whence {
+    Set,
+    Cur,
+    End,
+    Data,
+    Hole,
+}
+
+impl Whence {
+    pub fn from_raw(whence: c_int) -> Option<Self> {
+        match whence {
+            SEEK_SET => Some(Whence::Set),
+            SEEK_CUR => Some(Whence::Cur),
+            SEEK_END => Some(Whence::End),
+            SEEK_DATA => Some(Whence::Data),
+            SEEK_HOLE => Some(Whence::Hole),
+            _ => None,
+        }
+    }
+}
+
+
+// the data ranges of a sparse file, everything else up to `size` is a hole
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Extents {
+    size: u64,
+    data: Vec<Range<u64>>,
+}
+
+impl Extents {
+    pub fn new(size: u64) -> Self {
+        Self { size, data: Vec::new() }
+    }
+
+    pub fn with_data<T: IntoIterator<Item=Range<u64>>>(size: u64, data: T) -> Self {
+        let mut extents = Self::new(size);
+
+        for range in data {
+            extents.add_data(range);
+        }
+
+        extents
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn data(&self) -> &[Range<u64>] {
+        &self.data
+    }
+
+    pub fn add_data(&mut self, range: Range<u64>) {
+        let range = range.start..range.end.min(self.size);
+
+        if range.start >= range.end {
+            return;
+        }
+
+        let mut merged = range;
+
+        self.data.retain(|x| {
+            let touches = x.start <= merged.end && merged.start <= x.end;
+
+            if touches {
+                merged = merged.start.min(x.start)..merged.end.max(x.end);
+            }
+
+            !touches
+        });
+
+        let index = self.data.iter()
+            .position(|x| x.start > merged.start)
+            .unwrap_or(self.data.len());
+
+        self.data.insert(index, merged);
+    }
+
+    // answers SEEK_DATA and SEEK_HOLE, SEEK_SET and SEEK_END are taken as is
+    pub fn seek(&self, off: fuse::off_t, whence: Whence) -> Result<u64, Neg> {
+        let off: u64 = off.try_into().map_err(|_| neg!(-EINVAL))?;
+
+        match whence {
+            Whence::Set => Ok(off),
+            Whence::End => self.size.checked_add(off).ok_or(neg!(-EINVAL)),
+            Whence::Cur => Err(neg!(-EINVAL)),
+            Whence::Data => self.seek_data(off),
+            Whence::Hole => self.seek_hole(off),
+        }
+    }
+
+    fn seek_data(&self, off: u64) -> Result<u64, Neg> {
+        if off >= self.size {
+            return Err(neg!(-ENXIO));
+        }
+
+        self.data.iter()
+            .find(|x| x.end > off)
+            .map(|x| x.start.max(off))
+            .ok_or(neg!(-ENXIO))
+    }
+
+    fn seek_hole(&self, off: u64) -> Result<u64, Neg> {
+        if off >= self.size {
+            return Err(neg!(-ENXIO));
+        }
+
+        let mut pos = off;
+
+        for range in self.data.iter().filter(|x| x.end > off) {
+            if range.start > pos {
+                break;
+            }
+
+            pos = range.end;
+        }
+
+        // there is always a hole at the end of file
+        Ok(pos.min(self.size))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_and_holes() {
+        let extents = Extents::with_data(100, vec![60..80, 10..20, 15..30]);
+
+        assert_eq!(extents.data(), &[10..30, 60..80]);
+
+        assert_eq!(extents.seek(0, Whence::Data), Ok(10));
+        assert_eq!(extents.seek(25, Whence::Data), Ok(25));
+        assert_eq!(extents.seek(30, Whence::Data), Ok(60));
+        assert_eq!(extents.seek(80, Whence::Data), Err(neg!(-ENXIO)));
+
+        assert_eq!(extents.seek(0, Whence::Hole), Ok(0));
+        assert_eq!(extents.seek(10, Whence::Hole), Ok(30));
+        assert_eq!(extents.seek(70, Whence::Hole), Ok(80));
+        assert_eq!(extents.seek(100, Whence::Hole), Err(neg!(-ENXIO)));
+    }
+}
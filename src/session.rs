@@ -0,0 +1,245 @@
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::ptr::NonNull;
+use std::sync::{ Arc, Mutex, RwLock };
+
+use libc::c_int;
+
+use unwrap::unwrap;
+
+use crate::{ fuse, lowlevel, Context, Interrupt, LowLevelOperations, Neg, Notifier };
+use crate::notify::SessionPtr;
+
+// the headers of libfuse 3.12 and later turn it into a macro below FUSE_USE_VERSION 312
+#[cfg(fuse_loop_mt_32)]
+use fuse::fuse_session_loop_mt_32 as fuse_session_loop_mt;
+#[cfg(not(any(fuse_loop_mt_32, feature = "fuse_3_12")))]
+use fuse::fuse_session_loop_mt;
+
+
+// the caller and interrupt token of a request, both taken when it is dispatched,
+// libfuse frees the request itself as soon as it is answered
+pub struct Request {
+    ctx: fuse::fuse_ctx,
+    interrupt: Interrupt,
+}
+
+impl Request {
+    pub(crate) fn new(req: fuse::fuse_req_t) -> Self {
+        Self {
+            ctx: unsafe { *fuse::fuse_req_ctx(req) },
+            interrupt: Interrupt::of_request(req),
+        }
+    }
+
+    pub fn context(&self) -> Context {
+        Context {
+            uid: self.ctx.uid,
+            gid: self.ctx.gid,
+            pid: self.ctx.pid,
+            umask: self.ctx.umask,
+            private_data: None,
+        }
+    }
+
+    // supplementary groups of the caller
+    pub fn groups(&self) -> Result<Vec<fuse::gid_t>, Neg> {
+        self.context().groups()
+    }
+
+    pub fn interrupted(&self) -> bool {
+        self.interrupt.is_interrupted()
+    }
+
+    // interrupted along with the request through `fuse_req_interrupt_func`,
+    // the same token on every call
+    pub fn interrupt(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+
+    // `f` runs at most once, from the thread libfuse reads the interrupt on
+    pub fn on_interrupt<F: 'static + FnOnce() + Send>(&self, f: F) {
+        self.interrupt.on_interrupt(f);
+    }
+}
+
+
+// a low-level session, unmounted and destroyed on drop
+pub struct Session {
+    se: NonNull<fuse::fuse_session>,
+    ops: *mut lowlevel::UserData,
+    mounted: bool,
+    notify: Arc<RwLock<SessionPtr>>,
+}
+
+impl Session {
+    // `args` are the mount options, starting with the program name
+    pub fn new<T, U>(args: T, ops: U) -> Option<Self>
+        where T: Iterator<Item=String>,
+              U: 'static + LowLevelOperations + Send
+    {
+        let mut c_args: Vec<_> = args
+            .map(|arg| unwrap!(CString::new(arg)).into_raw())
+            .collect();
+
+        let mut fuse_args = fuse::fuse_args {
+            argc: unwrap!(c_args.len().try_into()),
+            argv: c_args.as_mut_ptr(),
+            allocated: 0,
+        };
+
+        let ops: *mut lowlevel::UserData = Box::into_raw(Box::new(Mutex::new(Box::new(ops))));
+        let ll_ops = lowlevel::fuse_lowlevel_ops_new();
+
+        unsafe {
+            let se = fuse::fuse_session_new(
+                &mut fuse_args,
+                &ll_ops,
+                std::mem::size_of::<fuse::fuse_lowlevel_ops>(),
+                ops.cast());
+
+            // libfuse copies the arguments it keeps
+            fuse::fuse_opt_free_args(&mut fuse_args);
+
+            for arg in c_args {
+                drop(CString::from_raw(arg));
+            }
+
+            match NonNull::new(se) {
+                Some(se) => Some(Self {
+                    se,
+                    ops,
+                    mounted: false,
+                    notify: Arc::new(RwLock::new(SessionPtr(se.as_ptr()))),
+                }),
+                None => {
+                    drop(Box::from_raw(ops));
+
+                    None
+                },
+            }
+        }
+    }
+
+    pub fn mount(&mut self, mountpoint: &str) -> Result<(), i32> {
+        let mountpoint = unwrap!(CString::new(mountpoint));
+
+        result(unsafe { fuse::fuse_session_mount(self.se.as_ptr(), mountpoint.as_ptr()) })?;
+        self.mounted = true;
+
+        Ok(())
+    }
+
+    // serves requests on the calling thread until unmounted or interrupted by a signal
+    pub fn run(&mut self) -> Result<(), i32> {
+        self.with_signal_handlers(|se| unsafe { fuse::fuse_session_loop(se) })
+    }
+
+    #[cfg(not(feature = "fuse_3_12"))]
+    pub fn run_mt(&mut self, clone_fd: bool, max_idle_threads: u32) -> Result<(), i32> {
+        let mut config = fuse::fuse_loop_config {
+            clone_fd: clone_fd.into(),
+            max_idle_threads,
+        };
+
+        self.with_signal_handlers(|se| unsafe { fuse_session_loop_mt(se, &mut config) })
+    }
+
+    #[cfg(feature = "fuse_3_12")]
+    pub fn run_mt(&mut self, clone_fd: bool, max_idle_threads: u32) -> Result<(), i32> {
+        self.run_loop_config(clone_fd, max_idle_threads, None)
+    }
+
+    // requires libfuse 3.12, also caps the number of threads serving requests
+    #[cfg(feature = "fuse_3_12")]
+    pub fn run_mt_max_threads(&mut self,
+        clone_fd: bool,
+        max_idle_threads: u32,
+        max_threads: u32) -> Result<(), i32>
+    {
+        self.run_loop_config(clone_fd, max_idle_threads, Some(max_threads))
+    }
+
+    // the loop config is opaque from FUSE_USE_VERSION 312 on
+    #[cfg(feature = "fuse_3_12")]
+    fn run_loop_config(&mut self,
+        clone_fd: bool,
+        max_idle_threads: u32,
+        max_threads: Option<u32>) -> Result<(), i32>
+    {
+        unsafe {
+            let config = fuse::fuse_loop_cfg_create();
+
+            if config.is_null() {
+                return Err(-1);
+            }
+
+            fuse::fuse_loop_cfg_set_clone_fd(config, clone_fd.into());
+            fuse::fuse_loop_cfg_set_idle_threads(config, max_idle_threads);
+
+            if let Some(max_threads) = max_threads {
+                fuse::fuse_loop_cfg_set_max_threads(config, max_threads);
+            }
+
+            let res = self.with_signal_handlers(|se| fuse::fuse_session_loop_mt(se, config));
+
+            fuse::fuse_loop_cfg_destroy(config);
+
+            res
+        }
+    }
+
+    // makes `run` return once the current request is handled
+    pub fn exit(&self) {
+        unsafe { fuse::fuse_session_exit(self.se.as_ptr()); }
+    }
+
+    pub fn notifier(&self) -> Notifier {
+        Notifier::new(self.notify.clone())
+    }
+
+    pub fn unmount(&mut self) {
+        if self.mounted {
+            unsafe { fuse::fuse_session_unmount(self.se.as_ptr()); }
+            self.mounted = false;
+        }
+    }
+
+    fn with_signal_handlers<F>(&mut self, f: F) -> Result<(), i32>
+        where F: FnOnce(*mut fuse::fuse_session) -> c_int
+    {
+        let se = self.se.as_ptr();
+
+        unsafe {
+            result(fuse::fuse_set_signal_handlers(se))?;
+
+            let res = f(se);
+
+            fuse::fuse_remove_signal_handlers(se);
+
+            result(res)
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        unwrap!(self.notify.write()).0 = std::ptr::null_mut();
+
+        self.unmount();
+
+        unsafe {
+            // calls `LowLevelOperations::destroy`, the operations are dropped after it
+            fuse::fuse_session_destroy(self.se.as_ptr());
+            drop(Box::from_raw(self.ops));
+        }
+    }
+}
+
+fn result(err: c_int) -> Result<(), i32> {
+    if err == 0 {
+        Ok(())
+    } else {
+        Err(err)
+    }
+}
@@ -0,0 +1,57 @@
+use std::convert::TryInto;
+
+use bitflags::bitflags;
+
+use libc::{ c_int, c_char };
+use libc::ERANGE;
+
+use unwrap::unwrap;
+
+
+bitflags! {
+    pub struct XattrFlags: c_int {
+        const CREATE = 1 << 0;
+        const REPLACE = 1 << 1;
+    }
+}
+
+
+// answers both the size probe (`size == 0`) and the actual read of an xattr value or name list
+pub(crate) unsafe fn reply(data: &[u8], buf: *mut c_char, size: usize) -> c_int {
+    let len = data.len();
+
+    if size == 0 {
+        unwrap!(len.try_into())
+    } else if len > size {
+        -ERANGE
+    } else {
+        buf.copy_from_nonoverlapping(data.as_ptr().cast(), len);
+
+        unwrap!(len.try_into())
+    }
+}
+
+// a name containing a nul byte can't be listed, it is left out
+pub(crate) fn name_list(names: &[String]) -> Vec<u8> {
+    let mut list = Vec::with_capacity(names.iter().map(|name| name.len() + 1).sum());
+
+    for name in names.iter().filter(|name| !name.as_bytes().contains(&0)) {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+    }
+
+    list
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_with_nul_are_left_out() {
+        let names = vec![String::from("user.a"), String::from("user.\0b"), String::from("user.c")];
+
+        assert_eq!(name_list(&names), b"user.a\0user.c\0");
+    }
+}